@@ -0,0 +1,66 @@
+use super::PasswordBackend;
+use crate::{
+    exec::{command_timeout, run_command, CommandError},
+    guard::check_id_chars,
+};
+use anyhow::{bail, Result};
+use async_trait::async_trait;
+use tracing::debug;
+
+/// Local Unix accounts, through `chpasswd` and `passwd -e`, unlocked with
+/// `faillock --reset` and `usermod -U`.
+pub struct ChpasswdBackend;
+
+#[async_trait]
+impl PasswordBackend for ChpasswdBackend {
+    fn name(&self) -> &'static str {
+        "chpasswd"
+    }
+
+    async fn reset_password(&self, id: &str, password: &str) -> Result<()> {
+        // one line per account on stdin, nothing may inject another line
+        check_id_chars(id)?;
+        if password.contains(['\n', '\r']) {
+            bail!("invalid characters in password for {}", id);
+        }
+        // user:password
+        let line = format!("{}:{}\n", id, password);
+        run_command(
+            "chpasswd",
+            &[],
+            &[],
+            Some(&line),
+            command_timeout("chpasswd"),
+        )
+        .await?;
+        Ok(())
+    }
+
+    async fn expire_password(&self, id: &str) -> Result<()> {
+        check_id_chars(id)?;
+        run_command("passwd", &["-e", id], &[], None, command_timeout("passwd")).await?;
+        Ok(())
+    }
+
+    async fn unlock_account(&self, id: &str) -> Result<()> {
+        check_id_chars(id)?;
+        let args = ["--user", id, "--reset"];
+        match run_command("faillock", &args, &[], None, command_timeout("faillock")).await {
+            Err(CommandError::Spawn { source, .. })
+                if source.kind() == std::io::ErrorKind::NotFound =>
+            {
+                debug!("faillock is not installed, skip it for {}", id);
+            }
+            r => r?,
+        }
+        run_command(
+            "usermod",
+            &["-U", id],
+            &[],
+            None,
+            command_timeout("usermod"),
+        )
+        .await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,97 @@
+use super::{blocking, update_file, PasswordBackend};
+use crate::guard::check_id_chars;
+use anyhow::Result;
+use async_trait::async_trait;
+use pwhash::bcrypt::{BcryptSetup, BcryptVariant};
+use std::path::PathBuf;
+use tracing::debug;
+
+/// An Apache htpasswd file, for course web services, passwords are hashed with bcrypt.
+///
+/// Missing entries are appended, like `htpasswd -B`.
+#[derive(Clone)]
+pub struct HtpasswdBackend {
+    path: PathBuf,
+}
+
+impl HtpasswdBackend {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self { path: path.into() }
+    }
+}
+
+impl HtpasswdBackend {
+    fn reset_password_blocking(&self, id: &str, password: &str) -> Result<()> {
+        check_id_chars(id)?;
+        let hash = pwhash::bcrypt::hash_with(
+            BcryptSetup {
+                variant: Some(BcryptVariant::V2y),
+                ..Default::default()
+            },
+            password,
+        )?;
+        let entry = format!("{}:{}", id, hash);
+        if !self.path.exists() {
+            std::fs::write(&self.path, "")?;
+        }
+        update_file(&self.path, |mut lines| {
+            match lines
+                .iter_mut()
+                .find(|line| line.split(':').next() == Some(id))
+            {
+                Some(line) => *line = entry,
+                None => lines.push(entry),
+            }
+            Ok(lines)
+        })
+    }
+}
+
+#[async_trait]
+impl PasswordBackend for HtpasswdBackend {
+    fn name(&self) -> &'static str {
+        "htpasswd"
+    }
+
+    async fn reset_password(&self, id: &str, password: &str) -> Result<()> {
+        let (this, id, password) = (self.clone(), id.to_string(), password.to_string());
+        blocking(move || this.reset_password_blocking(&id, &password)).await
+    }
+
+    async fn expire_password(&self, id: &str) -> Result<()> {
+        debug!("htpasswd has no password expiry, skip {}", id);
+        Ok(())
+    }
+
+    async fn unlock_account(&self, id: &str) -> Result<()> {
+        debug!("htpasswd has no account lock, skip {}", id);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_htpasswd_backend() -> Result<()> {
+        let path = std::env::temp_dir().join(format!("tenzin-htpasswd-{}", std::process::id()));
+        std::fs::write(&path, "1:$apr1$old\n")?;
+        let backend = HtpasswdBackend::new(&path);
+        backend.reset_password("1", "bupt1").await?;
+        backend.reset_password("233", "bupt233").await?;
+
+        let content = std::fs::read_to_string(&path)?;
+        let entries: Vec<(&str, &str)> = content
+            .lines()
+            .filter_map(|line| line.split_once(':'))
+            .collect();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].0, "1");
+        assert!(pwhash::bcrypt::verify("bupt1", entries[0].1));
+        assert_eq!(entries[1].0, "233");
+        assert!(pwhash::bcrypt::verify("bupt233", entries[1].1));
+        std::fs::remove_file(&path)?;
+        Ok(())
+    }
+}
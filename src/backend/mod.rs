@@ -0,0 +1,116 @@
+mod chpasswd;
+mod htpasswd;
+mod recording;
+mod shadow;
+
+pub use chpasswd::ChpasswdBackend;
+pub use htpasswd::HtpasswdBackend;
+pub use recording::{Record, RecordingBackend};
+pub use shadow::ShadowBackend;
+
+use crate::config::{get_config, BackendConfig};
+use anyhow::Result;
+use async_trait::async_trait;
+use parking_lot::Mutex;
+use std::{
+    io::Write,
+    os::unix::fs::{MetadataExt, OpenOptionsExt, PermissionsExt},
+    path::{Path, PathBuf},
+};
+
+/// Somewhere student credentials live, e.g. local Unix accounts or the
+/// htpasswd file of a course web service.
+///
+/// Implementations run inside the async runtime and must not block it.
+#[async_trait]
+pub trait PasswordBackend: Send + Sync {
+    fn name(&self) -> &'static str;
+    async fn reset_password(&self, id: &str, password: &str) -> Result<()>;
+    /// Forces the student to change the password on next login, if supported.
+    async fn expire_password(&self, id: &str) -> Result<()>;
+    /// Lifts a lock, e.g. after too many failed logins, keeping the password.
+    async fn unlock_account(&self, id: &str) -> Result<()>;
+}
+
+/// Builds the backends listed in `password.backends`, `chpasswd` if none.
+pub fn password_backends() -> Vec<Box<dyn PasswordBackend>> {
+    let configs = &get_config().password.backends;
+    if configs.is_empty() {
+        return vec![Box::new(ChpasswdBackend)];
+    }
+    configs.iter().map(build_backend).collect()
+}
+
+fn build_backend(config: &BackendConfig) -> Box<dyn PasswordBackend> {
+    match config {
+        BackendConfig::Chpasswd => Box::new(ChpasswdBackend),
+        BackendConfig::Shadow { path } => Box::new(ShadowBackend::new(path)),
+        BackendConfig::Htpasswd { path } => Box::new(HtpasswdBackend::new(path)),
+        BackendConfig::DryRun => Box::new(RecordingBackend::default()),
+    }
+}
+
+// Serializes read-modify-write cycles of the file backends.
+static FILE_LOCK: Mutex<()> = parking_lot::const_mutex(());
+
+/// Runs blocking work, e.g. file IO or password hashing, off the runtime.
+async fn blocking<T, F>(f: F) -> Result<T>
+where
+    F: FnOnce() -> Result<T> + Send + 'static,
+    T: Send + 'static,
+{
+    tokio::task::spawn_blocking(f).await?
+}
+
+/// Rewrites `path` with the lines returned by `f`, keeping its owner and
+/// permissions.
+///
+/// The new content is written to a temporary file which never has looser
+/// permissions than `path`, synced, then renamed over `path`.
+fn update_file<F>(path: &Path, f: F) -> Result<()>
+where
+    F: FnOnce(Vec<String>) -> Result<Vec<String>>,
+{
+    let _guard = FILE_LOCK.lock();
+    let content = std::fs::read_to_string(path)?;
+    let lines = f(content.lines().map(String::from).collect())?;
+    let mut content = lines.join("\n");
+    content.push('\n');
+    let metadata = std::fs::metadata(path)?;
+    let mode = metadata.permissions().mode();
+    let tmp = tmp_path(path);
+    // left behind by a crash, its permissions are unknown
+    match std::fs::remove_file(&tmp) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e.into()),
+        _ => {}
+    }
+    let mut file = std::fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(mode & 0o777)
+        .open(&tmp)?;
+    let result = (|| -> Result<()> {
+        std::os::unix::fs::fchown(&file, Some(metadata.uid()), Some(metadata.gid()))?;
+        // the umask may have narrowed the mode
+        file.set_permissions(std::fs::Permissions::from_mode(mode))?;
+        file.write_all(content.as_bytes())?;
+        file.sync_all()?;
+        std::fs::rename(&tmp, path)?;
+        Ok(())
+    })();
+    if result.is_err() {
+        let _ = std::fs::remove_file(&tmp);
+    }
+    result?;
+    // makes the rename itself durable
+    if let Some(dir) = path.parent().filter(|d| !d.as_os_str().is_empty()) {
+        std::fs::File::open(dir)?.sync_all()?;
+    }
+    Ok(())
+}
+
+pub(crate) fn tmp_path(path: &Path) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(".tenzin.tmp");
+    path.with_file_name(name)
+}
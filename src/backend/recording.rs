@@ -0,0 +1,59 @@
+use super::PasswordBackend;
+use anyhow::Result;
+use async_trait::async_trait;
+use parking_lot::Mutex;
+use std::sync::Arc;
+use tracing::info;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Record {
+    ResetPassword { id: String, password: String },
+    ExpirePassword { id: String },
+    UnlockAccount { id: String },
+}
+
+/// Changes nothing, only logs and records what would have been done.
+///
+/// Used as the `dry-run` backend and in tests, clones share their records.
+#[derive(Debug, Clone, Default)]
+pub struct RecordingBackend {
+    records: Arc<Mutex<Vec<Record>>>,
+}
+
+impl RecordingBackend {
+    pub fn records(&self) -> Vec<Record> {
+        self.records.lock().clone()
+    }
+}
+
+#[async_trait]
+impl PasswordBackend for RecordingBackend {
+    fn name(&self) -> &'static str {
+        "dry-run"
+    }
+
+    async fn reset_password(&self, id: &str, password: &str) -> Result<()> {
+        info!("dry-run: reset password for {}", id);
+        self.records.lock().push(Record::ResetPassword {
+            id: id.to_string(),
+            password: password.to_string(),
+        });
+        Ok(())
+    }
+
+    async fn expire_password(&self, id: &str) -> Result<()> {
+        info!("dry-run: set password expire for {}", id);
+        self.records
+            .lock()
+            .push(Record::ExpirePassword { id: id.to_string() });
+        Ok(())
+    }
+
+    async fn unlock_account(&self, id: &str) -> Result<()> {
+        info!("dry-run: unlock account {}", id);
+        self.records
+            .lock()
+            .push(Record::UnlockAccount { id: id.to_string() });
+        Ok(())
+    }
+}
@@ -0,0 +1,128 @@
+use super::{blocking, update_file, PasswordBackend};
+use crate::guard::check_id_chars;
+use anyhow::{bail, Result};
+use async_trait::async_trait;
+use chrono::Utc;
+use std::path::PathBuf;
+
+/// A file in `/etc/shadow` format, passwords are hashed with SHA-512 crypt.
+///
+/// Only existing entries are updated, accounts are never created.
+#[derive(Clone)]
+pub struct ShadowBackend {
+    path: PathBuf,
+}
+
+impl ShadowBackend {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self { path: path.into() }
+    }
+
+    // name:password:lastchg:min:max:warn:inactive:expire:reserved
+    fn update_entry<F>(&self, id: &str, f: F) -> Result<()>
+    where
+        F: FnOnce(&mut Vec<String>) -> Result<()>,
+    {
+        check_id_chars(id)?;
+        update_file(&self.path, |mut lines| {
+            let line = lines
+                .iter_mut()
+                .find(|line| line.split(':').next() == Some(id));
+            let line = match line {
+                Some(line) => line,
+                None => bail!("{} not found in {}", id, self.path.display()),
+            };
+            let mut fields: Vec<String> = line.split(':').map(String::from).collect();
+            if fields.len() != 9 {
+                bail!("malformed shadow entry for {}", id);
+            }
+            f(&mut fields)?;
+            *line = fields.join(":");
+            Ok(lines)
+        })
+    }
+}
+
+#[async_trait]
+impl PasswordBackend for ShadowBackend {
+    fn name(&self) -> &'static str {
+        "shadow"
+    }
+
+    async fn reset_password(&self, id: &str, password: &str) -> Result<()> {
+        let (this, id, password) = (self.clone(), id.to_string(), password.to_string());
+        blocking(move || {
+            let hash = pwhash::sha512_crypt::hash(password)?;
+            let days = Utc::now().timestamp() / 86400;
+            this.update_entry(&id, |fields| {
+                fields[1] = hash;
+                fields[2] = days.to_string();
+                Ok(())
+            })
+        })
+        .await
+    }
+
+    async fn expire_password(&self, id: &str) -> Result<()> {
+        let (this, id) = (self.clone(), id.to_string());
+        // same as `passwd -e`
+        blocking(move || {
+            this.update_entry(&id, |fields| {
+                fields[2] = "0".to_string();
+                Ok(())
+            })
+        })
+        .await
+    }
+
+    async fn unlock_account(&self, id: &str) -> Result<()> {
+        let (this, id) = (self.clone(), id.to_string());
+        // same as `usermod -U`, which drops the `!` prefix of the hash
+        blocking(move || {
+            this.update_entry(&id, |fields| {
+                if let Some(hash) = fields[1].strip_prefix('!') {
+                    if hash.is_empty() {
+                        bail!("{} has no password to unlock", id);
+                    }
+                    fields[1] = hash.to_string();
+                }
+                Ok(())
+            })
+        })
+        .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::PermissionsExt;
+
+    #[tokio::test]
+    async fn test_shadow_backend() -> Result<()> {
+        let path = std::env::temp_dir().join(format!("tenzin-shadow-{}", std::process::id()));
+        std::fs::write(
+            &path,
+            "root:*:19000:0:99999:7:::\n233:!:19000:0:99999:7:::\n1:!$6$x$y:19000:0:99999:7:::\n",
+        )?;
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o640))?;
+        let backend = ShadowBackend::new(&path);
+        backend.reset_password("233", "bupt233").await?;
+        backend.expire_password("233").await?;
+        assert!(backend.reset_password("404", "bupt404").await.is_err());
+        backend.unlock_account("1").await?;
+        backend.unlock_account("233").await?;
+
+        let content = std::fs::read_to_string(&path)?;
+        let lines: Vec<&str> = content.lines().collect();
+        assert_eq!(lines[0], "root:*:19000:0:99999:7:::");
+        let fields: Vec<&str> = lines[1].split(':').collect();
+        assert!(pwhash::sha512_crypt::verify("bupt233", fields[1]));
+        assert_eq!(fields[2], "0");
+        assert_eq!(lines[2], "1:$6$x$y:19000:0:99999:7:::");
+        let mode = std::fs::metadata(&path)?.permissions().mode();
+        assert_eq!(mode & 0o777, 0o640);
+        std::fs::remove_file(&path)?;
+        Ok(())
+    }
+}
@@ -1,41 +1,102 @@
 use std::{env, path::Path};
 
-use anyhow::Context;
+use anyhow::{bail, Context};
 use validator::validate_email;
 
-fn input_email(path: &Path) -> anyhow::Result<()> {
-    let mut email = String::new();
+/// The `.tenzin` file: the email on the first line, optionally the language
+/// of mails, e.g. `en`, on the second.
+#[derive(Debug, Default)]
+struct Tenzin {
+    email: String,
+    lang: Option<String>,
+}
+
+impl Tenzin {
+    fn parse(s: &str) -> Self {
+        let mut lines = s.lines().map(str::trim).filter(|l| !l.is_empty());
+        Self {
+            email: lines.next().unwrap_or_default().to_string(),
+            lang: lines.next().map(str::to_lowercase),
+        }
+    }
+
+    fn write(&self, path: &Path) -> anyhow::Result<()> {
+        let mut content = format!("{}\n", self.email);
+        if let Some(lang) = &self.lang {
+            content.push_str(&format!("{}\n", lang));
+        }
+        std::fs::write(path, content)?;
+        Ok(())
+    }
+}
+
+fn input_email() -> anyhow::Result<String> {
     loop {
         println!("Please input your email:");
-        std::io::stdin().read_line(&mut email)?;
-        email = email.trim().to_string();
+        let mut email = String::new();
+        if std::io::stdin().read_line(&mut email)? == 0 {
+            bail!("no email given");
+        }
+        let email = email.trim().to_string();
         if validate_email(&email) {
-            break;
-        } else {
-            eprintln!("Invalid email: {}", email);
+            return Ok(email);
         }
+        eprintln!("Invalid email: {}", email);
     }
-    std::fs::write(path, email)?;
-    Ok(())
 }
 
+// `-c` changes the email, `-l <lang>` sets the language of mails, `-l ""`
+// goes back to the default one
 fn main() -> anyhow::Result<()> {
     let home = env::var("HOME").context("Failed to get $HOME")?;
-    let force_change = { env::args().skip(1).any(|s| s.starts_with("-c")) };
+    let mut force_change = false;
+    let mut new_lang = None;
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        if arg.starts_with("-c") {
+            force_change = true;
+        } else if arg == "-l" || arg == "--lang" {
+            match args.next() {
+                Some(lang) => new_lang = Some(lang.trim().to_lowercase()),
+                None => bail!("{} needs a language, e.g. en or zh", arg),
+            }
+        }
+    }
+    if let Some(lang) = &new_lang {
+        if !lang.chars().all(|c| c.is_ascii_alphabetic() || c == '-') {
+            bail!("invalid language: {}", lang);
+        }
+    }
+
     let config_path = Path::new(&home).join(".tenzin");
+    let mut tenzin = if config_path.exists() {
+        Tenzin::parse(&std::fs::read_to_string(&config_path)?)
+    } else {
+        println!("{} has no config file", home);
+        Tenzin::default()
+    };
+    let mut changed = false;
     if force_change {
         println!("Force change email");
-        input_email(&config_path)?;
-    } else if config_path.exists() {
-        let email = std::fs::read_to_string(&config_path)?.trim().to_string();
-        if !validate_email(&email) {
-            eprintln!("invalid email: {}", email);
-            input_email(&config_path)?;
+        tenzin.email = input_email()?;
+        changed = true;
+    } else if !validate_email(&tenzin.email) {
+        if !tenzin.email.is_empty() {
+            eprintln!("invalid email: {}", tenzin.email);
         }
-        println!("Your email is: {}", email);
-    } else {
-        println!("{} has no config file", home);
-        input_email(&config_path)?;
+        tenzin.email = input_email()?;
+        changed = true;
+    }
+    if let Some(lang) = new_lang {
+        tenzin.lang = Some(lang).filter(|l| !l.is_empty());
+        changed = true;
+    }
+    if changed {
+        tenzin.write(&config_path)?;
+    }
+    println!("Your email is: {}", tenzin.email);
+    if let Some(lang) = &tenzin.lang {
+        println!("Your mails are in: {}", lang);
     }
     Ok(())
 }
@@ -1,11 +1,42 @@
+use anyhow::Context;
 use ed25519_zebra::{SigningKey, VerificationKey};
-use rand::thread_rng;
+use rand::{thread_rng, Rng};
+use std::io::Read;
 
 fn main() -> anyhow::Result<()> {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    // `tz-keygen --public [path]` prints the public key of an existing key,
+    // which goes to `[[sign.retired]]` when the key is rotated out. The key is
+    // read from the file at `path`, or stdin, never from the command line
+    // where other users could see it.
+    if args.first().map(String::as_str) == Some("--public") {
+        let key = match args.get(1).map(String::as_str) {
+            None | Some("-") => {
+                let mut key = String::new();
+                std::io::stdin().read_to_string(&mut key)?;
+                key
+            }
+            Some(path) => {
+                std::fs::read_to_string(path).with_context(|| format!("Failed to read {}", path))?
+            }
+        };
+        let sk_bytes = base64_url::decode(key.trim())?;
+        let sk = SigningKey::try_from(&sk_bytes[..])?;
+        let vk_bytes: [u8; 32] = VerificationKey::from(&sk).into();
+        println!("public_key = {:?}", base64_url::encode(&vk_bytes));
+        return Ok(());
+    }
+
     let sk = SigningKey::new(thread_rng());
     let sk_bytes: [u8; 32] = sk.into();
     let sk_str = base64_url::encode(&sk_bytes);
     println!("key = {:?}", sk_str);
+    let vk_bytes: [u8; 32] = VerificationKey::from(&sk).into();
+    println!("public_key = {:?}", base64_url::encode(&vk_bytes));
+    let encryption_key: [u8; 32] = thread_rng().gen();
+    println!("encryption_key = {:?}", base64_url::encode(&encryption_key));
+    let hash_key: [u8; 32] = thread_rng().gen();
+    println!("hash_key = {:?}", base64_url::encode(&hash_key));
     let sk_bytes = base64_url::decode(&sk_str)?;
     let decoded_sk = SigningKey::try_from(&sk_bytes[..])?;
     let message = b"hello world";
@@ -1,19 +1,99 @@
-use anyhow::Result;
+use anyhow::{bail, Result};
 use tenzin::{
-    config::{get_config, set_config, Config},
+    config::{get_config, load_config, prepare_config, reload, set_config, Role},
     mail::spin_up_mail_worker,
-    server::start_server,
+    server::{reload_tls, start_server},
     student::spin_up_student_worker,
 };
-use tracing::Level;
+use tokio::signal::unix::{signal, SignalKind};
+use tracing::{error, info, Level};
+
+const DEFAULT_CONFIG_PATH: &str = "config.toml";
+
+// `tz-server check-config [path] [--role web|worker]`, exits non-zero if the
+// config has problems for the role, both web and worker if none is given.
+fn check_config(path: &str, role: Role) -> ! {
+    let config = match load_config(path) {
+        Ok((config, _)) => config,
+        Err(e) => {
+            eprintln!("{:#}", e);
+            std::process::exit(1);
+        }
+    };
+    let errors = config.validate(role);
+    if errors.is_empty() {
+        println!("{} is ok", path);
+        std::process::exit(0);
+    }
+    for e in &errors {
+        eprintln!("{}", e);
+    }
+    eprintln!("{} problem(s) found in {}", errors.len(), path);
+    std::process::exit(1);
+}
+
+// Re-reads the config on SIGHUP, an invalid config is logged and the current
+// one kept.
+async fn reload_on_sighup(path: String, role: Role) -> Result<()> {
+    let mut hangup = signal(SignalKind::hangup())?;
+    while hangup.recv().await.is_some() {
+        info!("SIGHUP received, reloading {}", path);
+        if let Err(e) = reload(&path, role) {
+            error!("Failed to reload config, keeping the current one: {:#}", e);
+        }
+        reload_tls().await;
+    }
+    Ok(())
+}
 
 #[tokio::main]
 async fn main() -> Result<()> {
-    let config: Config = {
-        let s = std::fs::read_to_string("config.toml")?;
-        println!("{}", s);
-        toml::from_str(&s)?
+    // `tz-server web` only verifies links and needs `sign.public_key`,
+    // `tz-server worker` only sends mails and needs `sign.key` and `[mail]`,
+    // `tz-server` runs both. `--config <path>` and `--role <role>` may appear
+    // anywhere, the rest are positional.
+    let mut config_path = None;
+    let mut check_role = Role::All;
+    let mut args = Vec::new();
+    let mut iter = std::env::args().skip(1);
+    while let Some(arg) = iter.next() {
+        if arg == "--config" || arg == "-c" {
+            match iter.next() {
+                Some(path) => config_path = Some(path),
+                None => bail!("{} needs a path", arg),
+            }
+        } else if let Some(path) = arg.strip_prefix("--config=") {
+            config_path = Some(path.to_string());
+        } else if arg == "--role" {
+            check_role = match iter.next().as_deref() {
+                Some("web") => Role::Web,
+                Some("worker") => Role::Worker,
+                Some("all") => Role::All,
+                _ => bail!("--role needs `web`, `worker` or `all`"),
+            };
+        } else {
+            args.push(arg);
+        }
+    }
+    let role = match args.first().map(String::as_str) {
+        None => Role::All,
+        Some("web") => Role::Web,
+        Some("worker") => Role::Worker,
+        Some("check-config") => check_config(
+            args.get(1)
+                .or(config_path.as_ref())
+                .map_or(DEFAULT_CONFIG_PATH, String::as_str),
+            check_role,
+        ),
+        Some(arg) => bail!(
+            "unknown argument: {}, expected `web`, `worker` or `check-config [path] [--role <role>]`",
+            arg
+        ),
     };
+    let config_path = config_path.as_deref().unwrap_or(DEFAULT_CONFIG_PATH);
+
+    let (config, redacted) = prepare_config(config_path, role)?;
+    println!("{}", redacted);
     set_config(config);
 
     let file_appender =
@@ -25,8 +105,25 @@ async fn main() -> Result<()> {
         .with_ansi(false)
         .try_init();
 
+    tokio::spawn({
+        let path = config_path.to_string();
+        async move {
+            if let Err(e) = reload_on_sighup(path, role).await {
+                error!("Failed to handle SIGHUP: {}", e);
+            }
+        }
+    });
     spin_up_student_worker();
-    spin_up_mail_worker();
-    start_server().await?;
+    match role {
+        Role::All => {
+            spin_up_mail_worker();
+            start_server().await?;
+        }
+        Role::Web => start_server().await?,
+        Role::Worker => {
+            spin_up_mail_worker();
+            tokio::signal::ctrl_c().await?;
+        }
+    }
     Ok(())
 }
@@ -1,69 +1,109 @@
-use anyhow::{bail, Result};
-use std::{
-    io::Write,
-    process::{Command, Stdio},
+use crate::{
+    backend::{password_backends, PasswordBackend},
+    config::PasswordPolicy,
+    guard::check_account,
+    password::generate_password,
 };
+use anyhow::{Context, Result};
 use tracing::debug;
 
-pub fn reset_password_for(id: &str) -> Result<()> {
-    debug!("Resetting password for {}", id);
-    #[cfg(target_os = "macos")]
-    const CMD: &str = "cat";
-    #[cfg(not(target_os = "macos"))]
-    const CMD: &str = "chpasswd";
+/// Resets the password of `id` to one generated by `policy` and returns it.
+pub async fn reset_password_for(id: &str, policy: &PasswordPolicy) -> Result<String> {
+    check_account(id)?;
+    let password = generate_password(policy, id)?;
+    reset_password_with(&password_backends(), id, &password).await?;
+    Ok(password)
+}
 
-    let mut cmd = Command::new(CMD).stdin(Stdio::piped()).spawn()?;
-    {
-        let mut stdin = cmd.stdin.take().expect("Failed to open stdin");
-        // user:password
-        writeln!(stdin, "{}:bupt{}", id, id)?;
-    }
-    let status = cmd.wait()?;
-    if !status.success() {
-        bail!("Failed to reset password for {}", id);
-    }
+pub async fn set_password_expire_for(id: &str) -> Result<()> {
+    check_account(id)?;
+    set_password_expire_with(&password_backends(), id).await
+}
+
+pub async fn unlock_account_for(id: &str) -> Result<()> {
+    check_account(id)?;
+    unlock_account_with(&password_backends(), id).await
+}
 
+async fn reset_password_with(
+    backends: &[Box<dyn PasswordBackend>],
+    id: &str,
+    password: &str,
+) -> Result<()> {
+    debug!("Resetting password for {}", id);
+    for backend in backends {
+        backend
+            .reset_password(id, password)
+            .await
+            .with_context(|| format!("{} backend", backend.name()))?;
+    }
     Ok(())
 }
 
-pub fn set_password_expire_for(id: &str) -> Result<()> {
+async fn set_password_expire_with(backends: &[Box<dyn PasswordBackend>], id: &str) -> Result<()> {
     debug!("Setting password expire for {}", id);
-    #[cfg(target_os = "macos")]
-    const CMD: &str = "echo";
-    #[cfg(not(target_os = "macos"))]
-    const CMD: &str = "passwd";
-
-    let mut cmd = Command::new(CMD);
-
-    #[cfg(target_os = "macos")]
-    cmd.arg("passwd");
-
-    let status = cmd.args(["-e", id]).status()?;
-    if !status.success() {
-        bail!("Failed to set password expire for {}", id);
+    for backend in backends {
+        backend
+            .expire_password(id)
+            .await
+            .with_context(|| format!("{} backend", backend.name()))?;
     }
+    Ok(())
+}
 
+async fn unlock_account_with(backends: &[Box<dyn PasswordBackend>], id: &str) -> Result<()> {
+    debug!("Unlocking account {}", id);
+    for backend in backends {
+        backend
+            .unlock_account(id)
+            .await
+            .with_context(|| format!("{} backend", backend.name()))?;
+    }
     Ok(())
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::backend::{Record, RecordingBackend};
     use tracing::Level;
 
-    #[test]
-    fn test_reset_password() {
+    #[tokio::test]
+    async fn test_reset_password() {
         let _ = tracing_subscriber::fmt()
             .with_max_level(Level::DEBUG)
             .try_init();
-        reset_password_for("201800000000").unwrap();
+        let backend = RecordingBackend::default();
+        reset_password_with(
+            &[Box::new(backend.clone())],
+            "201800000000",
+            "bupt201800000000",
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            backend.records(),
+            vec![Record::ResetPassword {
+                id: "201800000000".to_string(),
+                password: "bupt201800000000".to_string(),
+            }]
+        );
     }
 
-    #[test]
-    fn test_set_password_expire() {
+    #[tokio::test]
+    async fn test_set_password_expire() {
         let _ = tracing_subscriber::fmt()
             .with_max_level(Level::DEBUG)
             .try_init();
-        set_password_expire_for("201800000000").unwrap();
+        let backend = RecordingBackend::default();
+        set_password_expire_with(&[Box::new(backend.clone())], "201800000000")
+            .await
+            .unwrap();
+        assert_eq!(
+            backend.records(),
+            vec![Record::ExpirePassword {
+                id: "201800000000".to_string(),
+            }]
+        );
     }
 }
@@ -1,68 +1,343 @@
-use ed25519_zebra::SigningKey;
-use once_cell::sync::OnceCell;
+use arc_swap::ArcSwapOption;
+use ed25519_zebra::{SigningKey, VerificationKey};
+use rand::Rng;
 use serde::{Deserialize, Deserializer};
+use std::{
+    borrow::Cow,
+    collections::HashMap,
+    fmt,
+    net::{IpAddr, SocketAddr},
+    path::PathBuf,
+    sync::Arc,
+};
 
-static CONFIG: OnceCell<Config> = OnceCell::new();
+mod duration;
+mod load;
+mod validate;
 
-pub fn get_config() -> &'static Config {
-    CONFIG.get().expect("config not initialized")
+use crate::locale::Catalogues;
+pub use duration::parse_duration;
+use duration::{deserialize_seconds, deserialize_seconds_map};
+use load::deserialize_from_str;
+pub use load::{load_config, prepare_config, reload, ENV_PREFIX};
+pub use validate::ConfigError;
+
+static CONFIG: ArcSwapOption<Config> = ArcSwapOption::const_empty();
+
+/// Returns the current config, hold it only as long as needed so a reload
+/// takes effect.
+pub fn get_config() -> Arc<Config> {
+    CONFIG.load_full().expect("config not initialized")
 }
 
+/// Sets or atomically replaces the config.
 pub fn set_config(config: Config) {
-    CONFIG.set(config).expect("config already initialized");
+    CONFIG.store(Some(Arc::new(config)));
+}
+
+/// Held by tests which set the config, as tests run in parallel.
+#[cfg(test)]
+pub(crate) static TEST_CONFIG_LOCK: tokio::sync::Mutex<()> = tokio::sync::Mutex::const_new(());
+
+/// Which part of the service a process runs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Role {
+    /// Both of the others.
+    All,
+    /// Verifies links and serves the pages, needs no mail account unless it
+    /// serves `/request`.
+    Web,
+    /// Reads requests and sends links, needs `[mail]` and `sign.key`.
+    Worker,
 }
 
 #[derive(Debug, Clone, Deserialize, Default)]
 pub struct Config {
+    /// Only optional for [`Role::Web`].
+    #[serde(default)]
     pub mail: MailConfig,
     pub sign: SignConfig,
     pub payload: PayloadConfig,
     pub student: StudentConfig,
     pub server: ServerConfig,
     pub log: LogConfig,
+    #[serde(default)]
+    pub password: PasswordConfig,
+    #[serde(default)]
+    pub guard: GuardConfig,
+    #[serde(default)]
+    pub command: CommandConfig,
+    #[serde(default)]
+    pub hooks: HooksConfig,
+    #[serde(default)]
+    pub branding: BrandingConfig,
+    #[serde(default)]
+    pub request_form: RequestFormConfig,
+    #[serde(default)]
+    pub rate_limit: RateLimitConfig,
+    #[serde(default)]
+    pub locale: LocaleConfig,
+    /// Courses served by this instance, see [`Config::tenants`].
+    #[serde(default)]
+    pub tenants: Vec<TenantConfig>,
+    /// Loaded from `locale.dir` along with the config.
+    #[serde(skip)]
+    pub catalogues: Arc<Catalogues>,
 }
 
+impl Config {
+    /// Configured tenants, or a single one built from `branding`,
+    /// `student.home_prefix`, `mail.directory` and `password.policy` if there
+    /// are none.
+    ///
+    /// The first tenant owns links issued before tenants existed.
+    pub fn tenants(&self) -> Cow<'_, [TenantConfig]> {
+        if !self.tenants.is_empty() {
+            return Cow::Borrowed(&self.tenants);
+        }
+        Cow::Owned(vec![TenantConfig {
+            name: DEFAULT_TENANT.to_string(),
+            course: self.branding.course.clone(),
+            subject_tag: self.branding.subject_tag.clone(),
+            home_prefix: self.student.home_prefix.clone(),
+            directory: None,
+            contact: self.branding.contact.clone(),
+            password_policy: None,
+            mails: None,
+            templates: String::new(),
+        }])
+    }
+
+    /// Longest window of all rate limits, hits older than it no longer count.
+    pub fn max_rate_limit_window(&self) -> u64 {
+        [
+            self.rate_limit.per_student,
+            self.rate_limit.per_sender,
+            self.rate_limit.per_ip,
+            self.request_form.per_ip,
+            self.request_form.per_id,
+        ]
+        .iter()
+        .map(|l| l.window)
+        .max()
+        .unwrap_or_default()
+    }
+
+    /// Tenant named `name`, the first tenant if `name` is empty.
+    pub fn tenant(&self, name: &str) -> Option<TenantConfig> {
+        let tenants = self.tenants();
+        if name.is_empty() {
+            return tenants.first().cloned();
+        }
+        tenants.iter().find(|t| t.name == name).cloned()
+    }
+}
+
+/// Name of the tenant used when none is configured.
+pub const DEFAULT_TENANT: &str = "default";
+
 #[derive(Debug, Clone, Deserialize, Default)]
 pub struct MailConfig {
     pub domain: String,
     pub send_domain: String,
+    #[serde(deserialize_with = "deserialize_from_str")]
     pub port: u16,
     pub user: String,
     pub password: String,
     pub directory: String,
+    /// Seconds between checks of the mailbox, e.g. `60` or `"1m"`.
+    #[serde(deserialize_with = "deserialize_seconds")]
     pub check_duration: u64,
+    /// Seconds between two sent mails.
+    #[serde(deserialize_with = "deserialize_seconds")]
     pub send_duration: u64,
 }
 
 #[derive(Debug, Clone, Deserialize)]
 pub struct SignConfig {
-    #[serde(deserialize_with = "deserialize_signing_key")]
-    pub key: SigningKey,
+    /// Private key, only needed by the process sending reset mails.
+    #[serde(default, deserialize_with = "deserialize_signing_key")]
+    pub key: Option<SigningKey>,
+    /// Public key of `key`, enough for the process verifying reset links.
+    #[serde(default, deserialize_with = "deserialize_optional_verification_key")]
+    pub public_key: Option<VerificationKey>,
+    /// Id of `key`, embedded in every payload it signs.
+    #[serde(default)]
+    pub key_id: String,
+    /// Keys which no longer sign new payloads but still verify outstanding ones.
+    #[serde(default)]
+    pub retired: Vec<RetiredKey>,
+    /// Symmetric key for encrypted payloads, shared by the mail worker and the
+    /// web server.
+    #[serde(default, deserialize_with = "deserialize_secret_key")]
+    pub encryption_key: Option<[u8; 32]>,
+    /// Key of the HMAC of the email in every payload, shared by the mail
+    /// worker and the web server, see [`crate::payload::email_hash`].
+    #[serde(default, deserialize_with = "deserialize_secret_key")]
+    pub hash_key: Option<[u8; 32]>,
+}
+
+impl SignConfig {
+    pub fn verification_key(&self, key_id: &str) -> Option<VerificationKey> {
+        if key_id == self.key_id {
+            return self
+                .public_key
+                .or_else(|| self.key.as_ref().map(VerificationKey::from));
+        }
+        self.retired
+            .iter()
+            .find(|k| k.id == key_id)
+            .map(|k| k.public_key)
+    }
 }
 
 impl Default for SignConfig {
     fn default() -> Self {
         Self {
-            key: SigningKey::new(rand::thread_rng()),
+            key: Some(SigningKey::new(rand::thread_rng())),
+            public_key: None,
+            key_id: String::new(),
+            retired: Vec::new(),
+            encryption_key: None,
+            hash_key: Some(rand::thread_rng().gen()),
         }
     }
 }
 
+#[derive(Debug, Clone, Deserialize)]
+pub struct RetiredKey {
+    pub id: String,
+    #[serde(deserialize_with = "deserialize_verification_key")]
+    pub public_key: VerificationKey,
+}
+
 #[derive(Debug, Clone, Deserialize, Default)]
 pub struct PayloadConfig {
-    pub oudate_secounds: u64,
+    /// Seconds a reset link stays valid, e.g. `"2h"`.
+    ///
+    /// The misspelled `oudate_secounds` is still accepted but deprecated.
+    #[serde(alias = "oudate_secounds", deserialize_with = "deserialize_seconds")]
+    pub outdate_seconds: u64,
+    /// File recording redeemed reset links, kept in memory only if empty.
+    #[serde(default)]
+    pub used_token_path: String,
+    /// Rejects payloads of version 1, turn on once links sent before the
+    /// upgrade have expired.
+    #[serde(default, deserialize_with = "deserialize_from_str")]
+    pub reject_legacy: bool,
+    #[serde(default)]
+    pub mode: PayloadMode,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum PayloadMode {
+    /// Links are signed but readable by anyone who sees them.
+    #[default]
+    Signed,
+    /// Links are signed, then encrypted with `sign.encryption_key`.
+    Encrypted,
 }
 
 #[derive(Debug, Clone, Deserialize, Default)]
 pub struct StudentConfig {
+    /// Student root when no `[[tenants]]` are configured.
+    #[serde(default)]
     pub home_prefix: String,
+    /// Seconds between two scans of `home_prefix`.
+    #[serde(deserialize_with = "deserialize_seconds")]
     pub walk_duration: u64,
 }
 
 #[derive(Debug, Clone, Deserialize, Default)]
 pub struct ServerConfig {
     pub domain: String,
+    #[serde(deserialize_with = "deserialize_from_str")]
     pub port: u16,
+    /// Where students reach the server, e.g. `https://tenzin.example.com/ics`,
+    /// `http://<domain>:<port><path_prefix>` if empty.
+    #[serde(default)]
+    pub public_base_url: String,
+    /// Path the routes are mounted under, e.g. `/ics`, for proxies which
+    /// don't strip it.
+    #[serde(default)]
+    pub path_prefix: String,
+    /// Proxies whose `X-Forwarded-For` is believed.
+    #[serde(default)]
+    pub trusted_proxies: Vec<IpAddr>,
+    /// Where to listen, `0.0.0.0:<port>` if unset.
+    #[serde(default)]
+    pub bind: Option<BindAddress>,
+    /// Serves HTTPS instead of HTTP if set.
+    #[serde(default)]
+    pub tls: Option<TlsConfig>,
+}
+
+impl ServerConfig {
+    pub fn base_url(&self) -> String {
+        if !self.public_base_url.is_empty() {
+            return self.public_base_url.trim_end_matches('/').to_string();
+        }
+        let scheme = if self.tls.is_some() { "https" } else { "http" };
+        format!(
+            "{}://{}:{}{}",
+            scheme, self.domain, self.port, self.path_prefix
+        )
+    }
+
+    pub fn bind_address(&self) -> BindAddress {
+        self.bind
+            .clone()
+            .unwrap_or_else(|| BindAddress::Tcp(SocketAddr::from(([0, 0, 0, 0], self.port))))
+    }
+
+    /// Public link to `path`, which starts with `/`.
+    pub fn link(&self, path: &str) -> String {
+        format!("{}{}", self.base_url(), path)
+    }
+}
+
+/// `0.0.0.0:8080`, `[::]:8080` or `unix:/run/tenzin.sock`.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub enum BindAddress {
+    Tcp(SocketAddr),
+    Unix(PathBuf),
+}
+
+impl TryFrom<String> for BindAddress {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        match s.strip_prefix("unix:") {
+            Some("") => Err("unix: needs a socket path".to_string()),
+            Some(path) => Ok(Self::Unix(PathBuf::from(path))),
+            None => s
+                .parse()
+                .map(Self::Tcp)
+                .map_err(|e| format!("invalid bind address {:?}: {}", s, e)),
+        }
+    }
+}
+
+impl fmt::Display for BindAddress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Tcp(addr) => write!(f, "{}", addr),
+            Self::Unix(path) => write!(f, "unix:{}", path.display()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct TlsConfig {
+    /// PEM certificate chain, reloaded with `key` on SIGHUP.
+    pub cert: String,
+    /// PEM private key.
+    pub key: String,
+    /// Also listens for plain HTTP here, redirecting every request to HTTPS.
+    #[serde(default)]
+    pub redirect_bind: Option<BindAddress>,
 }
 
 #[derive(Debug, Clone, Deserialize, Default)]
@@ -71,11 +346,369 @@ pub struct LogConfig {
     pub prefix: String,
 }
 
-fn deserialize_signing_key<'de, D>(deserializer: D) -> Result<SigningKey, D::Error>
+#[derive(Debug, Clone, Deserialize, Default)]
+pub struct PasswordConfig {
+    /// Where passwords are reset, in order, `chpasswd` if empty.
+    #[serde(default)]
+    pub backends: Vec<BackendConfig>,
+    #[serde(default)]
+    pub policy: PasswordPolicy,
+}
+
+/// How the password set by a reset is generated.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "kind", rename_all = "kebab-case")]
+pub enum PasswordPolicy {
+    /// `{id}` is replaced by the student id.
+    Template { template: String },
+    Random {
+        #[serde(deserialize_with = "deserialize_from_str")]
+        length: usize,
+        #[serde(default = "default_password_alphabet")]
+        alphabet: String,
+    },
+    /// Derived from HMAC-SHA256 of the student id.
+    Hmac {
+        secret: String,
+        #[serde(deserialize_with = "deserialize_from_str")]
+        length: usize,
+        #[serde(default = "default_password_alphabet")]
+        alphabet: String,
+    },
+}
+
+/// Random, a template such as `bupt{id}` lets anyone guess the password.
+impl Default for PasswordPolicy {
+    fn default() -> Self {
+        Self::Random {
+            length: 12,
+            alphabet: default_password_alphabet(),
+        }
+    }
+}
+
+// without look-alikes such as 0/O and 1/l/I
+fn default_password_alphabet() -> String {
+    "ABCDEFGHJKLMNPQRSTUVWXYZabcdefghijkmnopqrstuvwxyz23456789".to_string()
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "kind", rename_all = "kebab-case")]
+pub enum BackendConfig {
+    Chpasswd,
+    Shadow { path: String },
+    Htpasswd { path: String },
+    DryRun,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct CommandConfig {
+    /// Seconds an external command may run before it's killed, e.g. `10` or
+    /// `"10s"`.
+    #[serde(deserialize_with = "deserialize_seconds")]
+    pub timeout: u64,
+    /// Per-program timeouts overriding `timeout`, e.g. `chpasswd = "5s"`.
+    #[serde(deserialize_with = "deserialize_seconds_map")]
+    pub timeouts: HashMap<String, u64>,
+}
+
+impl Default for CommandConfig {
+    fn default() -> Self {
+        Self {
+            timeout: 10,
+            timeouts: HashMap::new(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Default)]
+pub struct HooksConfig {
+    /// Run in order after a successful reset.
+    #[serde(default)]
+    pub post_reset: Vec<HookConfig>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "kind", rename_all = "kebab-case")]
+pub enum HookConfig {
+    /// Runs `path` with `TENZIN_STUDENT_ID` and `TENZIN_ACTION` in its environment.
+    Script { path: String },
+    /// Kills every process of the student with `pkill -KILL -u`.
+    TerminateSessions,
+    /// `faillock --user <id> --reset`
+    ClearFaillock,
+    /// `pam_tally2 --user <id> --reset`
+    ClearPamTally,
+    /// `usermod -U <id>`
+    Unlock,
+}
+
+impl HookConfig {
+    /// The `kind` of the hook, without details such as the path of a script.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Self::Script { .. } => "script",
+            Self::TerminateSessions => "terminate-sessions",
+            Self::ClearFaillock => "clear-faillock",
+            Self::ClearPamTally => "clear-pam-tally",
+            Self::Unlock => "unlock",
+        }
+    }
+}
+
+impl fmt::Display for HookConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Script { path } => write!(f, "script {}", path),
+            Self::TerminateSessions => write!(f, "terminate-sessions"),
+            Self::ClearFaillock => write!(f, "clear-faillock"),
+            Self::ClearPamTally => write!(f, "clear-pam-tally"),
+            Self::Unlock => write!(f, "unlock"),
+        }
+    }
+}
+
+/// The `/request` page, where students start a reset with their id instead
+/// of a mail.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct RequestFormConfig {
+    /// Only served by processes with `sign.key`, which can send reset mails.
+    #[serde(deserialize_with = "deserialize_from_str")]
+    pub enabled: bool,
+    pub per_ip: RateLimit,
+    /// Per tenant and student id, whether or not the id exists.
+    pub per_id: RateLimit,
+}
+
+impl Default for RequestFormConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            per_ip: RateLimit {
+                max: 10,
+                window: 3600,
+            },
+            per_id: RateLimit {
+                max: 3,
+                window: 3600,
+            },
+        }
+    }
+}
+
+/// Limits on reset and unlock requests, throttled requests are logged.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct RateLimitConfig {
+    /// File keeping the counters across restarts, kept in memory only if empty.
+    pub path: String,
+    /// Mails sent per tenant and student id.
+    pub per_student: RateLimit,
+    /// Mails handled per sender address, registered or not.
+    pub per_sender: RateLimit,
+    /// Visits of reset and unlock links per client IP.
+    pub per_ip: RateLimit,
+}
+
+impl Default for RateLimitConfig {
+    fn default() -> Self {
+        Self {
+            path: String::new(),
+            per_student: RateLimit {
+                max: 5,
+                window: 86400,
+            },
+            per_sender: RateLimit {
+                max: 10,
+                window: 86400,
+            },
+            per_ip: RateLimit {
+                max: 60,
+                window: 3600,
+            },
+        }
+    }
+}
+
+/// At most `max` times in `window` seconds.
+#[derive(Debug, Clone, Copy, Deserialize)]
+pub struct RateLimit {
+    #[serde(deserialize_with = "deserialize_from_str")]
+    pub max: u32,
+    #[serde(deserialize_with = "deserialize_seconds")]
+    pub window: u64,
+}
+
+/// Names and texts shown to students, of the only course if no tenants are
+/// configured, then `course`, `contact` and `subject_tag` are required.
+#[derive(Debug, Clone, Deserialize, Default)]
+#[serde(default)]
+pub struct BrandingConfig {
+    /// Course name shown in pages and mails.
+    pub course: String,
+    pub contact: String,
+    /// See [`TenantConfig::subject_tag`].
+    pub subject_tag: String,
+    /// Replace the catalogue texts in `locale.default`, also for tenants
+    /// without their own.
+    pub mails: Option<MailTemplates>,
+}
+
+/// Subjects and bodies of the mails sent to students.
+///
+/// `{course}`, `{id}`, `{link}` and `{deadline}` are replaced by the course
+/// name, the student id, the link and the time the link expires.
+#[derive(Debug, Clone, Deserialize)]
+pub struct MailTemplates {
+    pub reset_subject: String,
+    pub reset_body: String,
+    pub unlock_subject: String,
+    pub unlock_body: String,
+}
+
+/// Languages of pages and mails.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct LocaleConfig {
+    /// Used when neither `Accept-Language` nor the student asks for a
+    /// language with a catalogue.
+    pub default: String,
+    /// Directory of `<lang>.toml` catalogues extending or replacing the
+    /// built-in `zh` and `en` ones, only the built-in ones if empty.
+    pub dir: String,
+}
+
+impl Default for LocaleConfig {
+    fn default() -> Self {
+        Self {
+            default: "zh".to_string(),
+            dir: String::new(),
+        }
+    }
+}
+
+/// A course sharing this instance with others.
+#[derive(Debug, Clone, Deserialize)]
+pub struct TenantConfig {
+    /// Short name, embedded in the links of this tenant.
+    pub name: String,
+    /// Course name shown in pages and mails, `name` if empty.
+    #[serde(default)]
+    pub course: String,
+    /// Mails titled `<subject_tag>#<id>` or `<subject_tag>#unlock#<id>` go to
+    /// this tenant.
+    pub subject_tag: String,
+    /// Directory holding the home directories of its students.
+    pub home_prefix: String,
+    /// IMAP folder its requests arrive in, `mail.directory` if unset.
+    #[serde(default)]
+    pub directory: Option<String>,
+    /// Shown to students when something goes wrong.
+    pub contact: String,
+    /// `password.policy` if unset.
+    #[serde(default)]
+    pub password_policy: Option<PasswordPolicy>,
+    /// `branding.mails` if unset.
+    #[serde(default)]
+    pub mails: Option<MailTemplates>,
+    /// Directory of pages overriding the built-in ones, e.g. `reset.html` or
+    /// `reset.en.html` for English only.
+    #[serde(default)]
+    pub templates: String,
+}
+
+impl TenantConfig {
+    pub fn directory<'a>(&'a self, config: &'a Config) -> &'a str {
+        self.directory.as_deref().unwrap_or(&config.mail.directory)
+    }
+
+    pub fn password_policy<'a>(&'a self, config: &'a Config) -> &'a PasswordPolicy {
+        self.password_policy
+            .as_ref()
+            .unwrap_or(&config.password.policy)
+    }
+
+    pub fn mails<'a>(&'a self, config: &'a Config) -> Option<&'a MailTemplates> {
+        self.mails.as_ref().or(config.branding.mails.as_ref())
+    }
+
+    pub fn course(&self) -> &str {
+        if self.course.is_empty() {
+            &self.name
+        } else {
+            &self.course
+        }
+    }
+}
+
+/// Which accounts may be reset at all.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct GuardConfig {
+    /// Requires every id to be a user in the system user database, turn off if
+    /// only non-Unix backends such as htpasswd are used.
+    #[serde(deserialize_with = "deserialize_from_str")]
+    pub require_system_user: bool,
+    #[serde(deserialize_with = "deserialize_from_str")]
+    pub min_uid: u32,
+    #[serde(deserialize_with = "deserialize_from_str")]
+    pub max_uid: u32,
+    /// Groups the user must be in one of, any group if empty.
+    pub allowed_groups: Vec<String>,
+    /// Accounts never reset, whatever their uid and groups.
+    pub deny: Vec<String>,
+}
+
+impl Default for GuardConfig {
+    fn default() -> Self {
+        Self {
+            require_system_user: true,
+            min_uid: 1000,
+            max_uid: 60000,
+            allowed_groups: Vec::new(),
+            deny: vec!["root".to_string()],
+        }
+    }
+}
+
+fn deserialize_signing_key<'de, D>(deserializer: D) -> Result<Option<SigningKey>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let s = String::deserialize(deserializer)?;
+    let s = base64_url::decode(&s).map_err(serde::de::Error::custom)?;
+    SigningKey::try_from(&s[..])
+        .map(Some)
+        .map_err(serde::de::Error::custom)
+}
+
+fn deserialize_verification_key<'de, D>(deserializer: D) -> Result<VerificationKey, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let s = String::deserialize(deserializer)?;
+    let s = base64_url::decode(&s).map_err(serde::de::Error::custom)?;
+    VerificationKey::try_from(&s[..]).map_err(serde::de::Error::custom)
+}
+
+fn deserialize_optional_verification_key<'de, D>(
+    deserializer: D,
+) -> Result<Option<VerificationKey>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    deserialize_verification_key(deserializer).map(Some)
+}
+
+fn deserialize_secret_key<'de, D>(deserializer: D) -> Result<Option<[u8; 32]>, D::Error>
 where
     D: Deserializer<'de>,
 {
     let s = String::deserialize(deserializer)?;
     let s = base64_url::decode(&s).map_err(serde::de::Error::custom)?;
-    SigningKey::try_from(&s[..]).map_err(serde::de::Error::custom)
+    <[u8; 32]>::try_from(&s[..])
+        .map(Some)
+        .map_err(|_| serde::de::Error::custom("key must be 32 bytes"))
 }
@@ -0,0 +1,106 @@
+use serde::{de, Deserialize, Deserializer};
+use std::collections::HashMap;
+
+const UNITS: &[(char, u64)] = &[('s', 1), ('m', 60), ('h', 3600), ('d', 86400)];
+
+/// Parses durations such as `90`, `30s`, `2h` or `1h30m` into seconds, a bare
+/// number is seconds.
+pub fn parse_duration(s: &str) -> Result<u64, String> {
+    let s = s.trim();
+    if s.is_empty() {
+        return Err("empty duration".to_string());
+    }
+    if let Ok(seconds) = s.parse() {
+        return Ok(seconds);
+    }
+    let mut seconds: u64 = 0;
+    let mut rest = s;
+    while !rest.is_empty() {
+        let digits = rest
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(rest.len());
+        if digits == 0 {
+            return Err(format!(
+                "invalid duration {:?}, expected e.g. 30s, 2h or 1d",
+                s
+            ));
+        }
+        let value: u64 = rest[..digits]
+            .parse()
+            .map_err(|_| format!("duration {:?} is too large", s))?;
+        let mut chars = rest[digits..].chars();
+        let unit = chars.next();
+        let scale = UNITS
+            .iter()
+            .find(|(u, _)| Some(*u) == unit)
+            .map(|(_, scale)| *scale)
+            .ok_or_else(|| format!("invalid duration {:?}, units are s, m, h and d", s))?;
+        seconds = value
+            .checked_mul(scale)
+            .and_then(|v| seconds.checked_add(v))
+            .ok_or_else(|| format!("duration {:?} is too large", s))?;
+        rest = chars.as_str();
+    }
+    Ok(seconds)
+}
+
+/// Deserializes seconds from an integer or a duration string, see
+/// [`parse_duration`].
+pub(super) fn deserialize_seconds<'de, D>(deserializer: D) -> Result<u64, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Seconds {
+        Integer(u64),
+        String(String),
+    }
+    match Seconds::deserialize(deserializer)? {
+        Seconds::Integer(seconds) => Ok(seconds),
+        Seconds::String(s) => parse_duration(&s).map_err(de::Error::custom),
+    }
+}
+
+/// Deserializes a map of seconds, each as [`deserialize_seconds`] does.
+pub(super) fn deserialize_seconds_map<'de, D>(
+    deserializer: D,
+) -> Result<HashMap<String, u64>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    struct Seconds(#[serde(deserialize_with = "deserialize_seconds")] u64);
+    let map = HashMap::<String, Seconds>::deserialize(deserializer)?;
+    Ok(map.into_iter().map(|(k, Seconds(s))| (k, s)).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_duration() {
+        assert_eq!(parse_duration("90"), Ok(90));
+        assert_eq!(parse_duration("30s"), Ok(30));
+        assert_eq!(parse_duration("5m"), Ok(300));
+        assert_eq!(parse_duration("2h"), Ok(7200));
+        assert_eq!(parse_duration("1d"), Ok(86400));
+        assert_eq!(parse_duration("1h30m"), Ok(5400));
+        assert!(parse_duration("").is_err());
+        assert!(parse_duration("2 hours").is_err());
+        assert!(parse_duration("h").is_err());
+        assert!(parse_duration("10y").is_err());
+        assert!(parse_duration("99999999999999999999d").is_err());
+    }
+
+    #[test]
+    fn test_deserialize_seconds() {
+        let config: crate::config::CommandConfig =
+            toml::from_str("timeout = \"10s\"\ntimeouts = { passwd = \"1m\", chpasswd = 5 }\n")
+                .unwrap();
+        assert_eq!(config.timeout, 10);
+        assert_eq!(config.timeouts["passwd"], 60);
+        assert_eq!(config.timeouts["chpasswd"], 5);
+    }
+}
@@ -0,0 +1,344 @@
+use super::{get_config, set_config, Config, Role};
+use crate::locale::Catalogues;
+use anyhow::{bail, Context, Result};
+use serde::{de, Deserialize, Deserializer};
+use std::{fmt, os::unix::fs::PermissionsExt, path::Path, str::FromStr, sync::Arc};
+use toml::Value;
+use tracing::{info, warn};
+
+/// Prefix of environment variables overriding config fields.
+///
+/// `TENZIN_MAIL__CHECK_DURATION=60` sets `mail.check_duration`, `__` separates
+/// path segments. Values are strings, numbers and booleans are parsed by their
+/// fields, only arrays and inline tables such as `["students"]` are TOML.
+pub const ENV_PREFIX: &str = "TENZIN_";
+const REDACTED: &str = "<redacted>";
+// fields holding secrets, at any depth
+const SECRET_KEYS: &[&str] = &["password", "key", "encryption_key", "hash_key", "secret"];
+// `<field>_file` loads `<field>` from a file
+const FILE_SUFFIX: &str = "_file";
+// (section, deprecated field, replacement)
+const DEPRECATED_FIELDS: &[(&str, &str, &str)] =
+    &[("payload", "oudate_secounds", "outdate_seconds")];
+
+/// Reads the config at `path`, then applies environment overrides, loads
+/// secret files and the catalogues in `locale.dir`.
+///
+/// Returns the config and a redacted TOML rendering of it, safe to print.
+pub fn load_config(path: impl AsRef<Path>) -> Result<(Config, String)> {
+    let path = path.as_ref();
+    let s = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read {}", path.display()))?;
+    let mut value: Value =
+        toml::from_str(&s).with_context(|| format!("Failed to parse {}", path.display()))?;
+    apply_env_overrides(&mut value, std::env::vars())?;
+    load_secret_files(&mut value, "")?;
+    for warning in check_deprecated_fields(&value)? {
+        // logging may not be set up yet
+        eprintln!("warning: {}", warning);
+        warn!("{}", warning);
+    }
+    let mut redacted = value.clone();
+    redact(&mut redacted);
+    let mut config: Config = value
+        .try_into()
+        .with_context(|| format!("Invalid config in {}", path.display()))?;
+    config.catalogues = Arc::new(Catalogues::load(&config.locale.dir).context("locale.dir")?);
+    Ok((config, toml::to_string(&redacted)?))
+}
+
+/// Loads and validates the config at `path` for `role`, returns it with its
+/// redacted rendering.
+pub fn prepare_config(path: &str, role: Role) -> Result<(Config, String)> {
+    let (config, redacted) = load_config(path)?;
+    let errors = config.validate(role);
+    if !errors.is_empty() {
+        let errors: Vec<String> = errors.iter().map(ToString::to_string).collect();
+        bail!(
+            "{} problem(s) found in {}: {}",
+            errors.len(),
+            path,
+            errors.join("; ")
+        );
+    }
+    Ok((config, redacted))
+}
+
+/// Replaces the current config with the one at `path`, an invalid config is
+/// returned as an error and the current one kept.
+///
+/// Changes which only take effect after a restart are logged.
+pub fn reload(path: &str, role: Role) -> Result<()> {
+    let (config, _) = prepare_config(path, role)?;
+    let old = get_config();
+    if config.server.bind_address() != old.server.bind_address()
+        || config.server.tls.is_some() != old.server.tls.is_some()
+    {
+        warn!("server.port, server.bind and server.tls changes need a restart");
+    }
+    // the routes are built once at startup
+    if config.server.path_prefix != old.server.path_prefix
+        || config.request_form.enabled != old.request_form.enabled
+    {
+        warn!("server.path_prefix and request_form.enabled changes need a restart");
+    }
+    if config.log.path != old.log.path || config.log.prefix != old.log.prefix {
+        warn!("log changes need a restart");
+    }
+    set_config(config);
+    info!("config reloaded from {}", path);
+    Ok(())
+}
+
+fn apply_env_overrides(
+    value: &mut Value,
+    vars: impl Iterator<Item = (String, String)>,
+) -> Result<()> {
+    for (name, raw) in vars {
+        let path = match name.strip_prefix(ENV_PREFIX) {
+            Some(path) => path.to_lowercase(),
+            None => continue,
+        };
+        let segments: Vec<&str> = path.split("__").collect();
+        // every field lives in a section, anything else isn't an override
+        if segments.len() < 2 || segments.iter().any(|s| s.is_empty()) {
+            continue;
+        }
+        let (field, sections) = segments.split_last().expect("at least two segments");
+        let mut table = value.as_table_mut().context("config is not a table")?;
+        for section in sections {
+            table = table
+                .entry(section.to_string())
+                .or_insert_with(|| Value::Table(Default::default()))
+                .as_table_mut()
+                .with_context(|| format!("{}: {} is not a section", name, section))?;
+        }
+        table.insert(field.to_string(), parse_env_value(&raw));
+    }
+    Ok(())
+}
+
+fn check_deprecated_fields(value: &Value) -> Result<Vec<String>> {
+    let mut warnings = Vec::new();
+    for (section, old, new) in DEPRECATED_FIELDS {
+        let table = match value.get(section).and_then(Value::as_table) {
+            Some(table) => table,
+            None => continue,
+        };
+        if table.contains_key(*old) {
+            if table.contains_key(*new) {
+                bail!(
+                    "{0}.{1} and {0}.{2} are both set, keep only {0}.{2}",
+                    section,
+                    old,
+                    new
+                );
+            }
+            warnings.push(format!(
+                "{0}.{1} is deprecated, rename it to {0}.{2}",
+                section, old, new
+            ));
+        }
+    }
+    Ok(warnings)
+}
+
+// `123456` must stay a string for e.g. `mail.password`, fields of other types
+// parse it with `deserialize_from_str`
+fn parse_env_value(raw: &str) -> Value {
+    if !raw.trim_start().starts_with(['[', '{']) {
+        return Value::String(raw.to_string());
+    }
+    toml::from_str::<toml::value::Table>(&format!("v = {}", raw))
+        .ok()
+        .and_then(|mut t| t.remove("v"))
+        .unwrap_or_else(|| Value::String(raw.to_string()))
+}
+
+/// Deserializes a number or boolean, or a string holding one, as environment
+/// overrides are strings.
+pub(super) fn deserialize_from_str<'de, D, T>(deserializer: D) -> Result<T, D::Error>
+where
+    D: Deserializer<'de>,
+    T: Deserialize<'de> + FromStr,
+    T::Err: fmt::Display,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum ValueOrString<T> {
+        Value(T),
+        String(String),
+    }
+    match ValueOrString::<T>::deserialize(deserializer)? {
+        ValueOrString::Value(value) => Ok(value),
+        ValueOrString::String(s) => s.trim().parse().map_err(de::Error::custom),
+    }
+}
+
+fn load_secret_files(value: &mut Value, prefix: &str) -> Result<()> {
+    match value {
+        Value::Table(table) => {
+            let file_keys: Vec<String> = table
+                .keys()
+                .filter(|k| k.ends_with(FILE_SUFFIX))
+                .cloned()
+                .collect();
+            for file_key in file_keys {
+                let key = file_key.trim_end_matches(FILE_SUFFIX).to_string();
+                let field = format!("{}{}", prefix, file_key);
+                let path = match table.remove(&file_key) {
+                    Some(Value::String(path)) => path,
+                    _ => bail!("{} must be a path", field),
+                };
+                let secret = read_secret_file(&path).with_context(|| field.clone())?;
+                table.insert(key, Value::String(secret));
+            }
+            for (key, value) in table.iter_mut() {
+                load_secret_files(value, &format!("{}{}.", prefix, key))?;
+            }
+        }
+        Value::Array(array) => {
+            for (i, value) in array.iter_mut().enumerate() {
+                let prefix = format!("{}[{}].", prefix.trim_end_matches('.'), i);
+                load_secret_files(value, &prefix)?;
+            }
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+/// Reads a secret, refusing files readable or writable by group or others.
+fn read_secret_file(path: &str) -> Result<String> {
+    let mode = std::fs::metadata(path)
+        .with_context(|| format!("Failed to read {}", path))?
+        .permissions()
+        .mode();
+    if mode & 0o077 != 0 {
+        bail!(
+            "{} has mode {:o}, secrets must not be accessible by group or others",
+            path,
+            mode & 0o777
+        );
+    }
+    let secret = std::fs::read_to_string(path)?;
+    Ok(secret.trim_end_matches(['\r', '\n']).to_string())
+}
+
+fn redact(value: &mut Value) {
+    match value {
+        Value::Table(table) => {
+            for (key, value) in table.iter_mut() {
+                if SECRET_KEYS.contains(&key.as_str()) {
+                    *value = Value::String(REDACTED.to_string());
+                } else {
+                    redact(value);
+                }
+            }
+        }
+        Value::Array(array) => array.iter_mut().for_each(redact),
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{GuardConfig, MailConfig, PayloadConfig, TEST_CONFIG_LOCK};
+    use ed25519_zebra::{SigningKey, VerificationKey};
+
+    #[test]
+    fn test_load_config() -> Result<()> {
+        let secret = std::env::temp_dir().join(format!("tenzin-secret-{}", std::process::id()));
+        std::fs::write(&secret, "hunter2\n")?;
+        let mut value: Value = toml::from_str(&format!(
+            "[mail]\ndomain = \"imap\"\nsend_domain = \"smtp\"\nuser = \"a@bupt.edu.cn\"\n\
+             password_file = {:?}\ncheck_duration = 30\nsend_duration = 10\n",
+            secret
+        ))?;
+
+        std::fs::set_permissions(&secret, std::fs::Permissions::from_mode(0o644))?;
+        assert!(load_secret_files(&mut value.clone(), "").is_err());
+        std::fs::set_permissions(&secret, std::fs::Permissions::from_mode(0o600))?;
+        load_secret_files(&mut value, "")?;
+        std::fs::remove_file(&secret)?;
+        assert_eq!(value["mail"]["password"].as_str(), Some("hunter2"));
+        assert!(value["mail"].get("password_file").is_none());
+
+        let vars = [
+            ("TENZIN_MAIL__CHECK_DURATION", "60"),
+            ("TENZIN_MAIL__PORT", "993"),
+            ("TENZIN_MAIL__PASSWORD", "123456"),
+            ("TENZIN_MAIL__DIRECTORY", "INBOX"),
+            ("TENZIN_GUARD__ALLOWED_GROUPS", "[\"students\"]"),
+            ("TENZIN_GUARD__REQUIRE_SYSTEM_USER", "false"),
+            ("TENZIN_PAYLOAD__MODE", "encrypted"),
+            ("TENZIN_STUDENT_ID", "233"),
+            ("PATH", "/bin"),
+        ];
+        let vars = vars.iter().map(|(k, v)| (k.to_string(), v.to_string()));
+        apply_env_overrides(&mut value, vars)?;
+        assert_eq!(value["mail"]["directory"].as_str(), Some("INBOX"));
+        assert_eq!(value["mail"]["password"].as_str(), Some("123456"));
+        let mail: MailConfig = value["mail"].clone().try_into()?;
+        assert_eq!(mail.password, "123456");
+        assert_eq!((mail.port, mail.check_duration), (993, 60));
+        let guard: GuardConfig = value["guard"].clone().try_into()?;
+        assert_eq!(guard.allowed_groups, ["students"]);
+        assert!(!guard.require_system_user);
+        assert_eq!(value["payload"]["mode"].as_str(), Some("encrypted"));
+        assert!(value.get("student_id").is_none());
+
+        assert!(check_deprecated_fields(&value)?.is_empty());
+        let mut payload: Value = toml::from_str("[payload]\noudate_secounds = \"2h\"\n")?;
+        assert_eq!(check_deprecated_fields(&payload)?.len(), 1);
+        let config: PayloadConfig = payload["payload"].clone().try_into()?;
+        assert_eq!(config.outdate_seconds, 7200);
+        payload["payload"]
+            .as_table_mut()
+            .unwrap()
+            .insert("outdate_seconds".to_string(), Value::Integer(60));
+        assert!(check_deprecated_fields(&payload).is_err());
+
+        redact(&mut value);
+        assert_eq!(value["mail"]["password"].as_str(), Some(REDACTED));
+        assert_eq!(value["mail"]["user"].as_str(), Some("a@bupt.edu.cn"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_reload() -> Result<()> {
+        let _lock = TEST_CONFIG_LOCK.blocking_lock();
+        set_config(Config::default());
+        let path = std::env::temp_dir().join(format!("tenzin-reload-{}", std::process::id()));
+        let path_str = path.to_str().unwrap();
+        let key = SigningKey::new(rand::thread_rng());
+        let public_key = <[u8; 32]>::from(VerificationKey::from(&key));
+        let valid = format!(
+            "[sign]\npublic_key = {:?}\nhash_key = {:?}\n\
+             [payload]\noutdate_seconds = \"2h\"\n\
+             [student]\nhome_prefix = \"tests/students\"\nwalk_duration = 60\n\
+             [server]\ndomain = \"localhost\"\nport = 8080\n\
+             [request_form]\nenabled = false\n\
+             [log]\npath = \"target/log\"\nprefix = \"tz-log\"\n\
+             [branding]\ncourse = \"ICS@BUPT\"\ncontact = \"ics@bupt.edu.cn\"\n\
+             subject_tag = \"ICS@BUPT\"\n",
+            base64_url::encode(&public_key),
+            base64_url::encode(&[7u8; 32]),
+        );
+
+        // no hash_key, the current config is kept
+        std::fs::write(&path, valid.replace("hash_key", "other_key"))?;
+        let e = reload(path_str, Role::Web).unwrap_err();
+        assert!(e.to_string().contains("sign.hash_key"), "{:#}", e);
+        assert_eq!(get_config().server.port, 0);
+
+        std::fs::write(&path, &valid)?;
+        reload(path_str, Role::Web)?;
+        std::fs::remove_file(&path)?;
+        let config = get_config();
+        assert_eq!(config.server.port, 8080);
+        assert_eq!(config.sign.hash_key, Some([7; 32]));
+        Ok(())
+    }
+}
@@ -0,0 +1,532 @@
+use super::{
+    BackendConfig, BindAddress, Config, HookConfig, MailTemplates, PasswordPolicy, PayloadMode,
+    Role,
+};
+use ed25519_zebra::VerificationKey;
+use std::{collections::HashSet, fmt, os::unix::fs::PermissionsExt, path::Path};
+
+/// A problem found by [`Config::validate`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConfigError {
+    /// Path of the field, e.g. `mail.check_duration`.
+    pub field: String,
+    pub message: String,
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.field, self.message)
+    }
+}
+
+#[derive(Default)]
+struct Errors(Vec<ConfigError>);
+
+impl Errors {
+    fn push(&mut self, field: impl Into<String>, message: impl Into<String>) {
+        self.0.push(ConfigError {
+            field: field.into(),
+            message: message.into(),
+        });
+    }
+
+    fn non_empty(&mut self, field: &str, value: &str) {
+        if value.trim().is_empty() {
+            self.push(field, "must not be empty");
+        }
+    }
+
+    fn positive(&mut self, field: &str, value: u64) {
+        if value == 0 {
+            self.push(field, "must be greater than 0");
+        }
+    }
+}
+
+impl Config {
+    /// Checks the whole config for a process running `role` and reports every
+    /// problem, not just the first.
+    pub fn validate(&self, role: Role) -> Vec<ConfigError> {
+        let mut errors = Errors::default();
+
+        let mail = &self.mail;
+        // the web server only sends mails for `/request`
+        let serves_form = self.request_form.enabled && self.sign.key.is_some();
+        if role != Role::Web || serves_form {
+            errors.non_empty("mail.send_domain", &mail.send_domain);
+            if mail.port == 0 {
+                errors.push("mail.port", "must not be 0");
+            }
+            if !validator::validate_email(&mail.user) {
+                errors.push("mail.user", format!("invalid email: {:?}", mail.user));
+            }
+            errors.non_empty("mail.password", &mail.password);
+        }
+        if role != Role::Web {
+            errors.non_empty("mail.domain", &mail.domain);
+            errors.non_empty("mail.directory", &mail.directory);
+            errors.positive("mail.check_duration", mail.check_duration);
+            if self.sign.key.is_none() {
+                errors.push("sign.key", "is required to send reset mails");
+            }
+        }
+
+        let sign = &self.sign;
+        if sign.verification_key(&sign.key_id).is_none() {
+            errors.push("sign", "either sign.key or sign.public_key is required");
+        }
+        if let (Some(key), Some(public_key)) = (&sign.key, &sign.public_key) {
+            if VerificationKey::from(key).as_ref() != public_key.as_ref() {
+                errors.push("sign.public_key", "doesn't match sign.key");
+            }
+        }
+        let mut key_ids = HashSet::from([sign.key_id.as_str()]);
+        for (i, retired) in sign.retired.iter().enumerate() {
+            if !key_ids.insert(&retired.id) {
+                errors.push(
+                    format!("sign.retired[{}].id", i),
+                    format!("duplicated key id {:?}", retired.id),
+                );
+            }
+        }
+        if sign.hash_key.is_none() {
+            errors.push("sign.hash_key", "is required, generate one with tz-keygen");
+        }
+        if self.payload.mode == PayloadMode::Encrypted && sign.encryption_key.is_none() {
+            errors.push(
+                "sign.encryption_key",
+                "is required when payload.mode is \"encrypted\"",
+            );
+        }
+
+        errors.positive("payload.outdate_seconds", self.payload.outdate_seconds);
+        check_parent_dir(
+            &mut errors,
+            "payload.used_token_path",
+            &self.payload.used_token_path,
+        );
+
+        let student = &self.student;
+        if self.tenants.is_empty() {
+            check_dir(&mut errors, "student.home_prefix", &student.home_prefix);
+        }
+        errors.positive("student.walk_duration", student.walk_duration);
+
+        let server = &self.server;
+        errors.non_empty("server.domain", &server.domain);
+        if server.port == 0 {
+            errors.push("server.port", "must not be 0");
+        }
+        if !server.public_base_url.is_empty() {
+            match url::Url::parse(&server.public_base_url) {
+                Ok(url) if matches!(url.scheme(), "http" | "https") => {
+                    if url.query().is_some() || url.fragment().is_some() {
+                        errors.push(
+                            "server.public_base_url",
+                            "must not have a query or fragment",
+                        );
+                    }
+                }
+                Ok(_) => errors.push("server.public_base_url", "must be an http or https URL"),
+                Err(e) => errors.push("server.public_base_url", format!("invalid URL: {}", e)),
+            }
+        }
+        if let Some(tls) = &server.tls {
+            for (field, path) in [("server.tls.cert", &tls.cert), ("server.tls.key", &tls.key)] {
+                if !Path::new(path).is_file() {
+                    errors.push(field, format!("{} is not a file", path));
+                }
+            }
+            if let BindAddress::Unix(_) = server.bind_address() {
+                errors.push("server.tls", "is not supported on a unix socket");
+            }
+            if tls.redirect_bind.as_ref() == Some(&server.bind_address()) {
+                errors.push("server.tls.redirect_bind", "must differ from server.bind");
+            }
+        }
+        let prefix = &server.path_prefix;
+        if !prefix.is_empty() && (!prefix.starts_with('/') || prefix.ends_with('/')) {
+            errors.push(
+                "server.path_prefix",
+                "must start with `/` and not end with `/`, e.g. \"/ics\"",
+            );
+        }
+
+        errors.non_empty("log.prefix", &self.log.prefix);
+        check_log_dir(&mut errors, &self.log.path);
+
+        check_policy(&mut errors, "password.policy", &self.password.policy);
+        for (i, backend) in self.password.backends.iter().enumerate() {
+            let field = format!("password.backends[{}].path", i);
+            match backend {
+                BackendConfig::Shadow { path } => {
+                    if !Path::new(path).is_file() {
+                        errors.push(field, format!("{} is not a file", path));
+                    }
+                }
+                BackendConfig::Htpasswd { path } => check_parent_dir(&mut errors, &field, path),
+                BackendConfig::Chpasswd | BackendConfig::DryRun => {}
+            }
+        }
+
+        if self.guard.min_uid > self.guard.max_uid {
+            errors.push("guard.min_uid", "must not be greater than guard.max_uid");
+        }
+
+        errors.positive("command.timeout", self.command.timeout);
+        for (program, timeout) in &self.command.timeouts {
+            errors.positive(&format!("command.timeouts.{}", program), *timeout);
+        }
+
+        for (i, hook) in self.hooks.post_reset.iter().enumerate() {
+            if let HookConfig::Script { path } = hook {
+                let executable = std::fs::metadata(path)
+                    .map(|m| m.is_file() && m.permissions().mode() & 0o111 != 0)
+                    .unwrap_or(false);
+                if !executable {
+                    errors.push(
+                        format!("hooks.post_reset[{}].path", i),
+                        format!("{} is not an executable file", path),
+                    );
+                }
+            }
+        }
+
+        check_parent_dir(&mut errors, "rate_limit.path", &self.rate_limit.path);
+        for (field, limit) in [
+            ("rate_limit.per_student", &self.rate_limit.per_student),
+            ("rate_limit.per_sender", &self.rate_limit.per_sender),
+            ("rate_limit.per_ip", &self.rate_limit.per_ip),
+            ("request_form.per_ip", &self.request_form.per_ip),
+            ("request_form.per_id", &self.request_form.per_id),
+        ] {
+            errors.positive(&format!("{}.max", field), limit.max as u64);
+            errors.positive(&format!("{}.window", field), limit.window);
+        }
+
+        let branding = &self.branding;
+        if self.tenants.is_empty() {
+            for (field, value) in [
+                ("branding.course", &branding.course),
+                ("branding.contact", &branding.contact),
+                ("branding.subject_tag", &branding.subject_tag),
+            ] {
+                if value.trim().is_empty() {
+                    errors.push(field, "is required when no tenants are configured");
+                }
+            }
+            if !branding.contact.trim().is_empty() && !validator::validate_email(&branding.contact)
+            {
+                errors.push(
+                    "branding.contact",
+                    format!("invalid email: {:?}", branding.contact),
+                );
+            }
+            if branding.subject_tag.contains('#') {
+                errors.push("branding.subject_tag", "must not contain `#`");
+            }
+        }
+        if let Some(mails) = &branding.mails {
+            check_mails(&mut errors, "branding.mails", mails);
+        }
+
+        if self.catalogues.pick(&self.locale.default) != Some(self.locale.default.as_str()) {
+            errors.push(
+                "locale.default",
+                format!("no catalogue for {:?}", self.locale.default),
+            );
+        }
+        if !self.locale.dir.is_empty() {
+            check_dir(&mut errors, "locale.dir", &self.locale.dir);
+        }
+        for lang in self.catalogues.languages() {
+            for key in ["mail.reset_body", "mail.unlock_body"] {
+                if self
+                    .catalogues
+                    .get(lang, key)
+                    .is_some_and(|b| !b.contains("{link}"))
+                {
+                    errors.push(format!("{} in {}.toml", key, lang), "must contain {link}");
+                }
+            }
+        }
+
+        let mut names = HashSet::new();
+        let mut tags = HashSet::new();
+        for (i, tenant) in self.tenants.iter().enumerate() {
+            let field = |name: &str| format!("tenants[{}].{}", i, name);
+            if tenant.name.is_empty()
+                || !tenant
+                    .name
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+            {
+                errors.push(
+                    field("name"),
+                    "must be non-empty letters, digits, `-` or `_`",
+                );
+            } else if !names.insert(tenant.name.as_str()) {
+                errors.push(field("name"), format!("duplicated name {:?}", tenant.name));
+            }
+            if tenant.subject_tag.trim().is_empty() || tenant.subject_tag.contains('#') {
+                errors.push(field("subject_tag"), "must be non-empty and without `#`");
+            } else if !tags.insert((tenant.directory(self), tenant.subject_tag.as_str())) {
+                errors.push(
+                    field("subject_tag"),
+                    format!(
+                        "{:?} is already used in folder {:?}",
+                        tenant.subject_tag,
+                        tenant.directory(self)
+                    ),
+                );
+            }
+            check_dir(&mut errors, &field("home_prefix"), &tenant.home_prefix);
+            errors.non_empty(&field("directory"), tenant.directory(self));
+            if !validator::validate_email(&tenant.contact) {
+                errors.push(
+                    field("contact"),
+                    format!("invalid email: {:?}", tenant.contact),
+                );
+            }
+            if let Some(policy) = &tenant.password_policy {
+                check_policy(&mut errors, &field("password_policy"), policy);
+            }
+            if let Some(mails) = &tenant.mails {
+                check_mails(&mut errors, &field("mails"), mails);
+            }
+            if !tenant.templates.is_empty() {
+                check_dir(&mut errors, &field("templates"), &tenant.templates);
+            }
+        }
+
+        errors.0
+    }
+}
+
+fn check_dir(errors: &mut Errors, field: &str, path: &str) {
+    if path.trim().is_empty() {
+        errors.push(field, "must not be empty");
+    } else if !Path::new(path).is_dir() {
+        errors.push(field, format!("{} is not a directory", path));
+    }
+}
+
+fn check_mails(errors: &mut Errors, field: &str, mails: &MailTemplates) {
+    let field = |name: &str| format!("{}.{}", field, name);
+    errors.non_empty(&field("reset_subject"), &mails.reset_subject);
+    errors.non_empty(&field("unlock_subject"), &mails.unlock_subject);
+    for (name, body) in [
+        ("reset_body", &mails.reset_body),
+        ("unlock_body", &mails.unlock_body),
+    ] {
+        if !body.contains("{link}") {
+            errors.push(field(name), "must contain {link}");
+        }
+    }
+}
+
+fn check_policy(errors: &mut Errors, field: &str, policy: &PasswordPolicy) {
+    let field = |name: &str| format!("{}.{}", field, name);
+    match policy {
+        PasswordPolicy::Template { template } => errors.non_empty(&field("template"), template),
+        PasswordPolicy::Random { length, alphabet } => {
+            errors.positive(&field("length"), *length as u64);
+            errors.non_empty(&field("alphabet"), alphabet);
+        }
+        PasswordPolicy::Hmac {
+            secret,
+            length,
+            alphabet,
+        } => {
+            errors.non_empty(&field("secret"), secret);
+            errors.positive(&field("length"), *length as u64);
+            errors.non_empty(&field("alphabet"), alphabet);
+        }
+    }
+}
+
+// files are written through temp files next to them, so their directory must
+// be writable
+fn check_parent_dir(errors: &mut Errors, field: &str, path: &str) {
+    if path.is_empty() {
+        return;
+    }
+    let parent = match Path::new(path).parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    if !parent.is_dir() {
+        errors.push(
+            field,
+            format!("directory {} doesn't exist", parent.display()),
+        );
+        return;
+    }
+    check_writable(errors, field, parent);
+}
+
+// the log directory is created on startup, so its nearest existing ancestor
+// must be a writable directory
+fn check_log_dir(errors: &mut Errors, path: &str) {
+    if path.trim().is_empty() {
+        errors.push("log.path", "must not be empty");
+        return;
+    }
+    let mut dir = Path::new(path);
+    while !dir.exists() {
+        dir = match dir.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent,
+            _ => Path::new("."),
+        };
+    }
+    if !dir.is_dir() {
+        errors.push("log.path", format!("{} is not a directory", dir.display()));
+        return;
+    }
+    check_writable(errors, "log.path", dir);
+}
+
+fn check_writable(errors: &mut Errors, field: &str, dir: &Path) {
+    let probe = dir.join(format!(".tenzin-check-{}", std::process::id()));
+    match std::fs::write(&probe, b"") {
+        Ok(()) => {
+            let _ = std::fs::remove_file(&probe);
+        }
+        Err(e) => errors.push(field, format!("{} is not writable: {}", dir.display(), e)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{
+        BrandingConfig, LogConfig, MailConfig, PayloadConfig, RequestFormConfig, ServerConfig,
+        SignConfig, StudentConfig, TenantConfig,
+    };
+
+    #[test]
+    fn test_validate() {
+        let errors = Config::default().validate(Role::All);
+        let fields: Vec<&str> = errors.iter().map(|e| e.field.as_str()).collect();
+        assert!(fields.contains(&"mail.check_duration"));
+        assert!(fields.contains(&"student.home_prefix"));
+        assert!(fields.contains(&"log.path"));
+        assert!(!fields.contains(&"sign"));
+        // no compiled-in course or contact to fall back to
+        assert!(fields.contains(&"branding.course"));
+        assert!(fields.contains(&"branding.contact"));
+
+        let config = Config {
+            mail: MailConfig {
+                domain: "imap.exmail.qq.com".to_string(),
+                send_domain: "smtp.exmail.qq.com".to_string(),
+                port: 993,
+                user: "user@bupt.edu.cn".to_string(),
+                password: "password".to_string(),
+                directory: "INBOX".to_string(),
+                check_duration: 30,
+                send_duration: 10,
+            },
+            payload: PayloadConfig {
+                outdate_seconds: 7200,
+                ..Default::default()
+            },
+            student: StudentConfig {
+                home_prefix: "tests/students".to_string(),
+                walk_duration: 60,
+            },
+            server: ServerConfig {
+                domain: "localhost".to_string(),
+                port: 8080,
+                public_base_url: "https://tenzin.bupt.edu.cn/ics/".to_string(),
+                path_prefix: "/ics".to_string(),
+                ..Default::default()
+            },
+            log: LogConfig {
+                path: "target/log".to_string(),
+                prefix: "tz-log".to_string(),
+            },
+            branding: BrandingConfig {
+                course: "ICS@BUPT".to_string(),
+                contact: "ics@bupt.edu.cn".to_string(),
+                subject_tag: "ICS@BUPT".to_string(),
+                mails: None,
+            },
+            ..Default::default()
+        };
+        assert_eq!(config.validate(Role::All), Vec::new());
+        assert_eq!(
+            config.server.link("/request"),
+            "https://tenzin.bupt.edu.cn/ics/request"
+        );
+        let direct = ServerConfig {
+            public_base_url: String::new(),
+            ..config.server.clone()
+        };
+        assert_eq!(direct.link("/request"), "http://localhost:8080/ics/request");
+
+        let fields = |errors: Vec<ConfigError>| -> Vec<String> {
+            errors.into_iter().map(|e| e.field).collect()
+        };
+        // only a web server serving `/request` needs a mail account
+        let web = Config {
+            mail: MailConfig::default(),
+            ..config.clone()
+        };
+        assert!(fields(web.validate(Role::Web)).contains(&"mail.password".to_string()));
+        let web = Config {
+            request_form: RequestFormConfig {
+                enabled: false,
+                ..Default::default()
+            },
+            ..web
+        };
+        assert_eq!(web.validate(Role::Web), Vec::new());
+        assert!(fields(web.validate(Role::Worker)).contains(&"mail.directory".to_string()));
+        let public = Config {
+            sign: SignConfig {
+                key: None,
+                public_key: config.sign.key.as_ref().map(VerificationKey::from),
+                ..Default::default()
+            },
+            ..config.clone()
+        };
+        assert_eq!(public.validate(Role::Web), Vec::new());
+        assert_eq!(fields(public.validate(Role::Worker)), ["sign.key"]);
+
+        let tenant = TenantConfig {
+            name: "ics".to_string(),
+            course: String::new(),
+            subject_tag: "ICS@BUPT".to_string(),
+            home_prefix: "tests/students".to_string(),
+            directory: None,
+            contact: "ics@bupt.edu.cn".to_string(),
+            password_policy: None,
+            mails: None,
+            templates: String::new(),
+        };
+        let config = Config {
+            student: StudentConfig {
+                home_prefix: String::new(),
+                walk_duration: 60,
+            },
+            tenants: vec![
+                tenant.clone(),
+                TenantConfig {
+                    name: "os".to_string(),
+                    contact: "os".to_string(),
+                    ..tenant
+                },
+            ],
+            // only used without tenants
+            branding: BrandingConfig::default(),
+            ..config
+        };
+        let fields: Vec<String> = config
+            .validate(Role::Web)
+            .into_iter()
+            .map(|e| e.field)
+            .collect();
+        assert_eq!(fields, ["tenants[1].subject_tag", "tenants[1].contact"]);
+    }
+}
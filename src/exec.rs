@@ -0,0 +1,159 @@
+use crate::config::get_config;
+use std::{fmt, process::Stdio, time::Duration};
+use tokio::{io::AsyncWriteExt, process::Command};
+use tracing::{debug, error};
+
+/// Why an external command didn't succeed.
+#[derive(Debug)]
+pub enum CommandError {
+    Spawn {
+        program: String,
+        source: std::io::Error,
+    },
+    Timeout {
+        program: String,
+        after: Duration,
+    },
+    Failed {
+        program: String,
+        code: Option<i32>,
+        stderr: String,
+    },
+}
+
+impl fmt::Display for CommandError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Spawn { program, source } => write!(f, "failed to run `{}`: {}", program, source),
+            Self::Timeout { program, after } => {
+                write!(f, "`{}` timed out after {:?}", program, after)
+            }
+            Self::Failed {
+                program,
+                code,
+                stderr,
+            } => {
+                match code {
+                    Some(code) => write!(f, "`{}` exited with code {}", program, code)?,
+                    None => write!(f, "`{}` was killed by a signal", program)?,
+                }
+                if !stderr.is_empty() {
+                    write!(f, ": {}", stderr)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl std::error::Error for CommandError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Spawn { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+/// Timeout of `program`, from `command.timeouts` or else `command.timeout`.
+pub fn command_timeout(program: &str) -> Duration {
+    let config = &get_config().command;
+    let secs = config
+        .timeouts
+        .get(program)
+        .copied()
+        .unwrap_or(config.timeout);
+    Duration::from_secs(secs)
+}
+
+/// Runs `program` without blocking the runtime, with extra `envs` and feeding it `stdin`.
+///
+/// The process is killed if it doesn't finish within `timeout`, its stderr is
+/// captured into the error.
+pub async fn run_command(
+    program: &str,
+    args: &[&str],
+    envs: &[(&str, &str)],
+    stdin: Option<&str>,
+    timeout: Duration,
+) -> Result<(), CommandError> {
+    debug!("running {} {:?}", program, args);
+    let spawn_error = |source| CommandError::Spawn {
+        program: program.to_string(),
+        source,
+    };
+    let mut child = Command::new(program)
+        .args(args)
+        .envs(envs.iter().copied())
+        .stdin(if stdin.is_some() {
+            Stdio::piped()
+        } else {
+            Stdio::null()
+        })
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()
+        .map_err(spawn_error)?;
+    let run = async {
+        if let (Some(input), Some(mut pipe)) = (stdin, child.stdin.take()) {
+            pipe.write_all(input.as_bytes()).await?;
+            // closing stdin lets the command finish
+            drop(pipe);
+        }
+        child.wait_with_output().await
+    };
+    let output = match tokio::time::timeout(timeout, run).await {
+        Ok(output) => output.map_err(spawn_error)?,
+        Err(_) => {
+            let e = CommandError::Timeout {
+                program: program.to_string(),
+                after: timeout,
+            };
+            error!("{}", e);
+            return Err(e);
+        }
+    };
+    if !output.status.success() {
+        let e = CommandError::Failed {
+            program: program.to_string(),
+            code: output.status.code(),
+            stderr: String::from_utf8_lossy(&output.stderr).trim().to_string(),
+        };
+        error!("{}", e);
+        return Err(e);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_run_command() {
+        let timeout = Duration::from_secs(5);
+        assert!(run_command("cat", &[], &[], Some("a:b\n"), timeout)
+            .await
+            .is_ok());
+        match run_command(
+            "sh",
+            &["-c", "echo $X >&2; exit 3"],
+            &[("X", "oops")],
+            None,
+            timeout,
+        )
+        .await
+        {
+            Err(CommandError::Failed { code, stderr, .. }) => {
+                assert_eq!(code, Some(3));
+                assert_eq!(stderr, "oops");
+            }
+            r => panic!("unexpected result: {:?}", r),
+        }
+        let r = run_command("sleep", &["10"], &[], None, Duration::from_millis(100)).await;
+        assert!(matches!(r, Err(CommandError::Timeout { .. })));
+        let r = run_command("no-such-command-tenzin", &[], &[], None, timeout).await;
+        assert!(matches!(r, Err(CommandError::Spawn { .. })));
+    }
+}
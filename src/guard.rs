@@ -0,0 +1,103 @@
+use crate::config::{get_config, GuardConfig};
+use anyhow::{bail, Result};
+use std::fmt;
+use tracing::warn;
+
+/// Why [`check_account`] refused an account, trying again won't help.
+#[derive(Debug)]
+pub struct AccountRefused(pub String);
+
+impl fmt::Display for AccountRefused {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "refused: {}", self.0)
+    }
+}
+
+impl std::error::Error for AccountRefused {}
+
+/// Refuses to touch `id` unless it's a plain student account.
+///
+/// Refusals are logged under the `tenzin::guard` target, so they stand out
+/// from ordinary failures, and are [`AccountRefused`] errors.
+pub fn check_account(id: &str) -> Result<()> {
+    if let Err(e) = check_account_with(&get_config().guard, id) {
+        warn!(target: "tenzin::guard", "refused to touch account {:?}: {}", id, e);
+        return Err(AccountRefused(e.to_string()).into());
+    }
+    Ok(())
+}
+
+/// Checks `id` can't break the `user:password` lines fed to `chpasswd` or
+/// written to credential files, nor be taken for an option by commands such as
+/// `passwd -e <id>`.
+pub fn check_id_chars(id: &str) -> Result<()> {
+    if id.is_empty() || id.starts_with('-') || id.chars().any(|c| c == ':' || c.is_control()) {
+        bail!("invalid characters in account name {:?}", id);
+    }
+    Ok(())
+}
+
+fn check_account_with(config: &GuardConfig, id: &str) -> Result<()> {
+    check_id_chars(id)?;
+    if config.deny.iter().any(|denied| denied == id) {
+        bail!("{} is in the deny list", id);
+    }
+    if !config.require_system_user {
+        return Ok(());
+    }
+    let user = match uzers::get_user_by_name(id) {
+        Some(user) => user,
+        None => bail!("{} is not a system user", id),
+    };
+    let uid = user.uid();
+    if uid < config.min_uid || uid > config.max_uid {
+        bail!(
+            "uid {} of {} is outside {}..={}",
+            uid,
+            id,
+            config.min_uid,
+            config.max_uid
+        );
+    }
+    if !config.allowed_groups.is_empty() {
+        let groups = uzers::get_user_groups(id, user.primary_group_id()).unwrap_or_default();
+        let allowed = groups.iter().any(|group| {
+            config
+                .allowed_groups
+                .iter()
+                .any(|allowed| group.name() == allowed.as_str())
+        });
+        if !allowed {
+            bail!("{} is in none of the allowed groups", id);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check_account() {
+        let config = GuardConfig {
+            require_system_user: false,
+            ..Default::default()
+        };
+        assert!(check_account_with(&config, "2018211000").is_ok());
+        assert!(check_account_with(&config, "root").is_err());
+        assert!(check_account_with(&config, "").is_err());
+        assert!(check_account_with(&config, "a:b").is_err());
+        assert!(check_account_with(&config, "a\nroot").is_err());
+        assert!(check_account_with(&config, "-a").is_err());
+        assert!(check_account_with(&config, "a-b").is_ok());
+
+        let config = GuardConfig {
+            deny: Vec::new(),
+            ..Default::default()
+        };
+        // uid 0
+        assert!(check_account_with(&config, "root").is_err());
+        assert!(check_account_with(&config, "no-such-user-tenzin").is_err());
+    }
+}
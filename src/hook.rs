@@ -0,0 +1,118 @@
+use crate::{
+    config::{get_config, HookConfig},
+    exec::{command_timeout, run_command, CommandError},
+};
+use tracing::{error, info};
+
+/// A hook which failed after a successful reset, shown to the student.
+///
+/// Only the kind of the hook, the error itself is logged as it may reveal
+/// details of the server.
+#[derive(Debug, Clone)]
+pub struct HookFailure {
+    pub hook: &'static str,
+}
+
+/// Runs `hooks.post_reset` in order, failures are collected and don't stop
+/// later hooks, the reset itself is never rolled back.
+pub async fn run_post_reset_hooks(id: &str) -> Vec<HookFailure> {
+    let mut failures = Vec::new();
+    for hook in &get_config().hooks.post_reset {
+        match run_hook(hook, id).await {
+            Ok(()) => info!("post reset hook {} done for {}", hook, id),
+            Err(e) => {
+                error!("post reset hook {} failed for {}: {}", hook, id, e);
+                failures.push(HookFailure { hook: hook.kind() });
+            }
+        }
+    }
+    failures
+}
+
+async fn run_hook(hook: &HookConfig, id: &str) -> Result<(), CommandError> {
+    let (program, args): (&str, Vec<&str>) = match hook {
+        HookConfig::Script { path } => {
+            let envs = [("TENZIN_STUDENT_ID", id), ("TENZIN_ACTION", "reset")];
+            return run_command(path, &[], &envs, None, command_timeout(path)).await;
+        }
+        HookConfig::TerminateSessions => ("pkill", vec!["-KILL", "-u", id]),
+        HookConfig::ClearFaillock => ("faillock", vec!["--user", id, "--reset"]),
+        HookConfig::ClearPamTally => ("pam_tally2", vec!["--user", id, "--reset"]),
+        HookConfig::Unlock => ("usermod", vec!["-U", id]),
+    };
+    let r = run_command(program, &args, &[], None, command_timeout(program)).await;
+    ignore_expected(hook, r)
+}
+
+fn ignore_expected(hook: &HookConfig, r: Result<(), CommandError>) -> Result<(), CommandError> {
+    match r {
+        // pkill found no process, nothing to terminate
+        Err(CommandError::Failed { code: Some(1), .. })
+            if matches!(hook, HookConfig::TerminateSessions) =>
+        {
+            Ok(())
+        }
+        r => r,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{set_config, Config, HooksConfig, TEST_CONFIG_LOCK};
+    use std::{fs, os::unix::fs::PermissionsExt, path::Path};
+
+    fn script(path: &Path, body: &str) -> HookConfig {
+        fs::write(path, format!("#!/bin/sh\n{}\n", body)).unwrap();
+        fs::set_permissions(path, fs::Permissions::from_mode(0o755)).unwrap();
+        HookConfig::Script {
+            path: path.to_string_lossy().into_owned(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_run_post_reset_hooks() {
+        let _lock = TEST_CONFIG_LOCK.lock().await;
+        let dir = std::env::temp_dir().join(format!("tenzin-hooks-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let log = dir.join("log");
+        let post_reset = vec![
+            script(&dir.join("fail.sh"), "exit 2"),
+            script(
+                &dir.join("log.sh"),
+                &format!(
+                    "echo \"$TENZIN_STUDENT_ID $TENZIN_ACTION\" >> {}",
+                    log.display()
+                ),
+            ),
+            HookConfig::Script {
+                path: dir.join("missing.sh").to_string_lossy().into_owned(),
+            },
+        ];
+        set_config(Config {
+            hooks: HooksConfig { post_reset },
+            ..Default::default()
+        });
+
+        let failures = run_post_reset_hooks("2018000").await;
+        let kinds: Vec<_> = failures.iter().map(|f| f.hook).collect();
+        assert_eq!(kinds, ["script", "script"]);
+        // the failing hook didn't stop the next one
+        assert_eq!(fs::read_to_string(&log).unwrap(), "2018000 reset\n");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_ignore_expected() {
+        let failed = |code| {
+            Err(CommandError::Failed {
+                program: "pkill".to_string(),
+                code: Some(code),
+                stderr: String::new(),
+            })
+        };
+        assert!(ignore_expected(&HookConfig::TerminateSessions, failed(1)).is_ok());
+        assert!(ignore_expected(&HookConfig::TerminateSessions, failed(2)).is_err());
+        assert!(ignore_expected(&HookConfig::Unlock, failed(1)).is_err());
+    }
+}
@@ -1,6 +1,16 @@
+pub mod backend;
 pub mod command;
 pub mod config;
+pub mod exec;
+pub mod guard;
+pub mod hook;
+pub mod locale;
 pub mod mail;
+pub mod metrics;
+pub mod password;
 pub mod payload;
+pub mod rate_limit;
 pub mod server;
 pub mod student;
+pub mod timestamp_file;
+pub mod token_store;
@@ -0,0 +1,223 @@
+use crate::config::get_config;
+use anyhow::{bail, Context, Result};
+use std::{collections::HashMap, sync::Arc};
+use toml::Value;
+
+// (language, catalogue) shipped with the binary
+const BUILTIN: &[(&str, &str)] = &[
+    ("zh", include_str!("../locales/zh.toml")),
+    ("en", include_str!("../locales/en.toml")),
+];
+
+/// Texts of pages and mails by language, each keyed by `<section>.<name>`,
+/// e.g. `reset.title`.
+#[derive(Debug, Clone)]
+pub struct Catalogues(HashMap<String, HashMap<String, String>>);
+
+impl Default for Catalogues {
+    fn default() -> Self {
+        let mut catalogues = HashMap::new();
+        for (lang, s) in BUILTIN {
+            let texts = parse_catalogue(s).expect("invalid built-in catalogue");
+            catalogues.insert(lang.to_string(), texts);
+        }
+        Self(catalogues)
+    }
+}
+
+impl Catalogues {
+    /// The built-in catalogues with every `<lang>.toml` in `dir` merged over
+    /// them, a new language if there is no built-in one.
+    pub fn load(dir: &str) -> Result<Self> {
+        let mut catalogues = Self::default();
+        if dir.is_empty() {
+            return Ok(catalogues);
+        }
+        let entries = std::fs::read_dir(dir).with_context(|| format!("Failed to read {}", dir))?;
+        for entry in entries {
+            let path = entry?.path();
+            if path.extension().and_then(|e| e.to_str()) != Some("toml") {
+                continue;
+            }
+            let lang = match path.file_stem().and_then(|s| s.to_str()) {
+                Some(lang) => lang.to_lowercase(),
+                None => continue,
+            };
+            let texts = std::fs::read_to_string(&path)
+                .map_err(anyhow::Error::from)
+                .and_then(|s| parse_catalogue(&s))
+                .with_context(|| format!("Invalid catalogue {}", path.display()))?;
+            catalogues.0.entry(lang).or_default().extend(texts);
+        }
+        Ok(catalogues)
+    }
+
+    pub fn languages(&self) -> impl Iterator<Item = &str> {
+        self.0.keys().map(String::as_str)
+    }
+
+    /// Text `key` in `lang`, if its catalogue has it.
+    pub fn get(&self, lang: &str, key: &str) -> Option<&str> {
+        self.0.get(lang)?.get(key).map(String::as_str)
+    }
+
+    /// The language with a catalogue matching the tag `lang`, e.g. `zh` for
+    /// `zh-CN`.
+    pub fn pick(&self, lang: &str) -> Option<&str> {
+        let lang = lang.trim().to_lowercase();
+        let primary = lang.split('-').next().unwrap_or_default();
+        let (lang, _) = [lang.as_str(), primary]
+            .into_iter()
+            .find_map(|l| self.0.get_key_value(l))?;
+        Some(lang)
+    }
+
+    /// The language preferred by `accept`, an `Accept-Language` value, among
+    /// those with a catalogue.
+    pub fn negotiate(&self, accept: &str) -> Option<&str> {
+        let mut ranges: Vec<(&str, f32)> = accept
+            .split(',')
+            .filter_map(|range| {
+                let mut parts = range.split(';');
+                let tag = parts.next()?.trim();
+                let q = parts
+                    .find_map(|p| p.trim().strip_prefix("q="))
+                    .map_or(Some(1.0), |q| q.trim().parse().ok())?;
+                (!tag.is_empty() && tag != "*" && q > 0.0).then_some((tag, q))
+            })
+            .collect();
+        // stable, so equal weights keep the client's order
+        ranges.sort_by(|a, b| b.1.total_cmp(&a.1));
+        ranges.iter().find_map(|(tag, _)| self.pick(tag))
+    }
+}
+
+// flattens `[section] name = "text"` into `section.name`
+fn parse_catalogue(s: &str) -> Result<HashMap<String, String>> {
+    let table: toml::value::Table = toml::from_str(s)?;
+    let mut texts = HashMap::new();
+    for (section, value) in table {
+        let section_table = match value {
+            Value::Table(table) => table,
+            _ => bail!("{} must be a section", section),
+        };
+        for (name, value) in section_table {
+            match value {
+                Value::String(text) => texts.insert(format!("{}.{}", section, name), text),
+                _ => bail!("{}.{} must be a string", section, name),
+            };
+        }
+    }
+    Ok(texts)
+}
+
+/// Texts in one language, with `{<var>}` replaced by the value of each var.
+#[derive(Debug, Clone)]
+pub struct Messages {
+    catalogues: Arc<Catalogues>,
+    lang: String,
+    vars: Vec<(&'static str, String)>,
+}
+
+impl Messages {
+    /// Texts in `lang`, which must have a catalogue, see [`Catalogues::pick`].
+    pub fn new(lang: &str, vars: Vec<(&'static str, String)>) -> Self {
+        Self {
+            catalogues: get_config().catalogues.clone(),
+            lang: lang.to_string(),
+            vars,
+        }
+    }
+
+    pub fn var(mut self, name: &'static str, value: impl Into<String>) -> Self {
+        self.vars.push((name, value.into()));
+        self
+    }
+
+    pub fn lang(&self) -> &str {
+        &self.lang
+    }
+
+    pub fn vars(&self) -> &[(&'static str, String)] {
+        &self.vars
+    }
+
+    /// Text `key`, from the catalogue of `locale.default` if missing in the
+    /// language, the key itself if missing there too.
+    pub fn text(&self, key: &str) -> String {
+        let default = &get_config().locale.default;
+        let text = self
+            .catalogues
+            .get(&self.lang, key)
+            .or_else(|| self.catalogues.get(default, key))
+            .unwrap_or(key);
+        fill(text, &self.vars)
+    }
+}
+
+/// Replaces `{<name>}` in `template` by the value of each var.
+pub fn fill<V: AsRef<str>>(template: &str, vars: &[(&str, V)]) -> String {
+    let mut text = template.to_string();
+    for (name, value) in vars {
+        text = text.replace(&format!("{{{}}}", name), value.as_ref());
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_catalogues() -> Result<()> {
+        let dir = std::env::temp_dir().join(format!("tenzin-locales-{}", std::process::id()));
+        std::fs::create_dir_all(&dir)?;
+        std::fs::write(
+            dir.join("en.toml"),
+            "[mail]\nreset_subject = \"[{course}] Reset\"\n",
+        )?;
+        std::fs::write(
+            dir.join("ja.toml"),
+            "[mail]\nreset_subject = \"リセット\"\n",
+        )?;
+        let catalogues = Catalogues::load(dir.to_str().unwrap())?;
+        std::fs::remove_dir_all(&dir)?;
+
+        assert_eq!(
+            catalogues.get("en", "mail.reset_subject"),
+            Some("[{course}] Reset")
+        );
+        assert_eq!(
+            catalogues.get("en", "mail.unlock_subject"),
+            Some("Unlock account")
+        );
+        assert_eq!(catalogues.get("ja", "mail.reset_subject"), Some("リセット"));
+        assert_eq!(catalogues.get("ja", "mail.unlock_subject"), None);
+        // built-in catalogues have the same keys
+        for (lang, other) in [("zh", "en"), ("en", "zh")] {
+            assert!(catalogues.0[lang]
+                .keys()
+                .all(|k| catalogues.get(other, k).is_some()));
+        }
+
+        assert_eq!(catalogues.pick("zh-CN"), Some("zh"));
+        assert_eq!(catalogues.pick("fr"), None);
+        assert_eq!(
+            catalogues.negotiate("fr, en-US;q=0.8, zh;q=0.9"),
+            Some("zh")
+        );
+        assert_eq!(catalogues.negotiate("EN-gb,zh;q=0.5"), Some("en"));
+        assert_eq!(catalogues.negotiate("zh;q=0, *"), None);
+        assert!(parse_catalogue("title = \"x\"").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_fill() {
+        let vars = [("course", "ICS@BUPT"), ("link", "http://localhost/reset/x")];
+        assert_eq!(
+            fill("[{course}] {link} {deadline}", &vars),
+            "[ICS@BUPT] http://localhost/reset/x {deadline}"
+        );
+    }
+}
@@ -1,5 +1,9 @@
-use crate::config::{get_config, MailConfig};
-use anyhow::{Context, Result};
+use crate::{
+    config::{get_config, MailConfig, TenantConfig},
+    metrics,
+    payload::Purpose,
+};
+use anyhow::{bail, Context, Result};
 use async_imap::Session;
 use async_native_tls::TlsStream;
 use futures::{StreamExt, TryStreamExt};
@@ -8,7 +12,11 @@ use tokio::net::TcpStream;
 #[derive(Debug)]
 pub struct ResetPasswordRequest {
     pub email: String,
+    /// Name of the tenant whose subject tag the mail was titled with.
+    pub tenant: String,
     pub student_id: String,
+    /// `Unlock` if the subject is `ICS@BUPT#unlock#<id>`.
+    pub purpose: Purpose,
 }
 
 #[derive(Debug)]
@@ -23,15 +31,16 @@ pub struct UnreadMails {
     pub raw: Vec<RawEmail>,
 }
 
-pub async fn pull_all_unread_from_directory() -> Result<UnreadMails> {
+/// Pulls unread mails from the folder of every tenant.
+pub async fn pull_all_unread() -> Result<UnreadMails> {
+    let config = get_config();
     let MailConfig {
         domain,
         port,
         user,
         password,
-        directory,
         ..
-    } = &get_config().mail;
+    } = &config.mail;
 
     let tls = async_native_tls::TlsConnector::new();
 
@@ -44,20 +53,51 @@ pub async fn pull_all_unread_from_directory() -> Result<UnreadMails> {
         .await
         .map_err(|e| anyhow::anyhow!("Failed to login to IMAP server: {}", e.0))?;
 
-    imap_session.select(&directory).await?;
-
     let mut parsed = Vec::new();
     let mut raw = Vec::new();
+    let tenants = config.tenants();
+    let mut directories: Vec<&str> = tenants.iter().map(|t| t.directory(&config)).collect();
+    directories.sort_unstable();
+    directories.dedup();
+    for directory in directories {
+        let tenants: Vec<&TenantConfig> = tenants
+            .iter()
+            .filter(|t| t.directory(&config) == directory)
+            .collect();
+        pull_unread_from_directory(
+            &mut imap_session,
+            directory,
+            &tenants,
+            &mut parsed,
+            &mut raw,
+        )
+        .await
+        .with_context(|| format!("Failed to pull mails from {}", directory))?;
+    }
+
+    Ok(UnreadMails { parsed, raw })
+}
+
+async fn pull_unread_from_directory(
+    imap_session: &mut Session<TlsStream<TcpStream>>,
+    directory: &str,
+    tenants: &[&TenantConfig],
+    parsed: &mut Vec<ResetPasswordRequest>,
+    raw: &mut Vec<RawEmail>,
+) -> Result<()> {
+    imap_session.select(directory).await?;
 
     let ids = imap_session.search("UNSEEN").await?;
+    metrics::MAILS_FETCHED.add(ids.len() as u64);
 
     for id in &ids {
         let mut raw_mail = None;
-        match fetch_parse_mail_header(&mut imap_session, *id, &mut raw_mail).await {
+        match fetch_parse_mail_header(imap_session, *id, tenants, &mut raw_mail).await {
             Ok(req) => {
                 parsed.push(req);
             }
             Err(e) => {
+                metrics::MAIL_PARSE_FAILURES.inc();
                 raw.push(RawEmail {
                     id: *id,
                     raw: raw_mail,
@@ -67,6 +107,9 @@ pub async fn pull_all_unread_from_directory() -> Result<UnreadMails> {
         }
     }
 
+    if ids.is_empty() {
+        return Ok(());
+    }
     let seq = ids
         .iter()
         .map(|id| id.to_string())
@@ -77,13 +120,13 @@ pub async fn pull_all_unread_from_directory() -> Result<UnreadMails> {
         .await?
         .try_collect()
         .await?;
-
-    Ok(UnreadMails { parsed, raw })
+    Ok(())
 }
 
 async fn fetch_parse_mail_header(
     imap_session: &mut Session<TlsStream<TcpStream>>,
     id: u32,
+    tenants: &[&TenantConfig],
     raw: &mut Option<String>,
 ) -> Result<ResetPasswordRequest> {
     let range = format!("{}:{}", id, id);
@@ -97,12 +140,15 @@ async fn fetch_parse_mail_header(
     let header = String::from_utf8_lossy(header).to_string();
     *raw = Some(header.clone());
     let mut email = None;
-    let mut student_id = None;
+    let mut subject = None;
     for kv in header.split("\r\n") {
         if let Some((key, value)) = kv.split_once(": ") {
             match key {
                 "From" => {
-                    let email_full = value.split(' ').last().context("Failed to parse email")?;
+                    let email_full = value
+                        .split(' ')
+                        .next_back()
+                        .context("Failed to parse email")?;
                     email = Some(
                         email_full
                             .trim_start_matches('<')
@@ -110,17 +156,74 @@ async fn fetch_parse_mail_header(
                             .to_string(),
                     );
                 }
-                "Subject" => {
-                    let student = value.trim().trim_start_matches("ICS@BUPT#").trim_end();
-                    student_id = Some(student.to_string());
-                }
+                "Subject" => subject = Some(parse_subject(value, tenants)?),
                 _ => {}
             }
         }
     }
 
+    let (tenant, purpose, student_id) = subject.context("Failed to parse student id")?;
     Ok(ResetPasswordRequest {
         email: email.context("Failed to parse email")?,
-        student_id: student_id.context("Failed to parse student id")?,
+        tenant: tenant.name.clone(),
+        student_id,
+        purpose,
     })
 }
+
+// `<tag>#<id>` or `<tag>#unlock#<id>`, matched against the tags of `tenants`
+fn parse_subject<'a>(
+    subject: &str,
+    tenants: &[&'a TenantConfig],
+) -> Result<(&'a TenantConfig, Purpose, String)> {
+    let subject = subject.trim();
+    let (tag, rest) = subject
+        .split_once('#')
+        .with_context(|| format!("no subject tag in {:?}", subject))?;
+    let tenant = match tenants.iter().find(|t| t.subject_tag == tag) {
+        Some(tenant) => tenant,
+        None => bail!("unknown subject tag {:?}", tag),
+    };
+    let (purpose, student) = match rest.split_once('#') {
+        Some((action, student)) if action.eq_ignore_ascii_case("unlock") => {
+            (Purpose::Unlock, student)
+        }
+        _ => (Purpose::Reset, rest),
+    };
+    Ok((tenant, purpose, student.trim().to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_subject() -> Result<()> {
+        let tenant = |name: &str, tag: &str| TenantConfig {
+            name: name.to_string(),
+            course: String::new(),
+            subject_tag: tag.to_string(),
+            home_prefix: String::new(),
+            directory: None,
+            contact: String::new(),
+            password_policy: None,
+            mails: None,
+            templates: String::new(),
+        };
+        let (ics, os) = (tenant("ics", "ICS@BUPT"), tenant("os", "OS@BUPT"));
+        let tenants = [&ics, &os];
+        let (t, purpose, id) = parse_subject("ICS@BUPT#2018211000", &tenants)?;
+        assert_eq!(
+            (t.name.as_str(), purpose, id.as_str()),
+            ("ics", Purpose::Reset, "2018211000")
+        );
+        let (t, purpose, id) = parse_subject(" OS@BUPT#unlock#2018211000 ", &tenants)?;
+        assert_eq!(
+            (t.name.as_str(), purpose, id.as_str()),
+            ("os", Purpose::Unlock, "2018211000")
+        );
+        assert!(parse_subject("DB@BUPT#2018211000", &tenants).is_err());
+        assert!(parse_subject("2018211000", &tenants).is_err());
+        Ok(())
+    }
+}
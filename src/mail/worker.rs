@@ -1,9 +1,15 @@
 use std::sync::Once;
 
 use crate::{
-    config::get_config, mail::send_mail, payload::build_payload, student::check_student_email,
+    config::get_config,
+    locale::{fill, Messages},
+    mail::send_mail,
+    metrics,
+    payload::{build_payload, Purpose},
+    rate_limit::check_rate_limit,
+    student::{check_student_email, get_student_lang},
 };
-use anyhow::Result;
+use anyhow::{Context, Result};
 use chrono::{Duration, Local};
 use once_cell::sync::OnceCell;
 use tokio::{
@@ -12,7 +18,7 @@ use tokio::{
 };
 use tracing::{debug, error, info};
 
-use super::pull_all_unread_from_directory;
+use super::pull_all_unread;
 
 static WORKER_TX: OnceCell<Sender<()>> = OnceCell::new();
 
@@ -32,10 +38,9 @@ pub async fn shutdown_mail_worker() {
 }
 
 async fn mail_worker() {
+    metrics::set_mail_worker_running();
     let (tx, mut rx) = channel(1);
     WORKER_TX.set(tx).expect("worker already initialized");
-    let send_duration = get_config().mail.send_duration;
-    let check_duration = get_config().mail.check_duration;
     select! {
         _ = rx.recv() => {
             info!("rx: mail worker stopped");
@@ -43,6 +48,9 @@ async fn mail_worker() {
         _ = async {
             loop {
                 info!("mail_worker running");
+                // read every round, so a reloaded config takes effect
+                let send_duration = get_config().mail.send_duration;
+                let check_duration = get_config().mail.check_duration;
                 if let Err(e) = process_mails(send_duration).await {
                     error!("Failed to process_mails: {}", e);
                 }
@@ -55,14 +63,38 @@ async fn mail_worker() {
 }
 
 async fn process_mails(send_duration: u64) -> Result<()> {
-    let mails = pull_all_unread_from_directory().await?;
+    let mails = pull_all_unread().await?;
+    metrics::IMAP_POLL_LAST_SUCCESS.set_now();
     debug!(mails=?mails);
+    let limits = get_config().rate_limit.clone();
     for req in mails.parsed {
-        if !check_student_email(&req.student_id, &req.email) {
+        // counted before the email is checked, so strangers are limited too
+        if !check_rate_limit(
+            &format!("mail-sender:{}", req.email.to_lowercase()),
+            limits.per_sender,
+        )
+        .await
+        {
+            continue;
+        }
+        if !check_student_email(&req.tenant, &req.student_id, &req.email) {
             error!("invalid student email: {:?}", req);
+            metrics::MAILS_REJECTED.inc();
             continue;
         }
-        if let Err(e) = send_reset_mail(&req.email, &req.student_id).await {
+        if !check_rate_limit(
+            &format!("mail-student:{}/{}", req.tenant, req.student_id),
+            limits.per_student,
+        )
+        .await
+        {
+            continue;
+        }
+        let sent = match req.purpose {
+            Purpose::Reset => send_reset_mail(&req.tenant, &req.email, &req.student_id).await,
+            Purpose::Unlock => send_unlock_mail(&req.tenant, &req.email, &req.student_id).await,
+        };
+        if let Err(e) = sent {
             error!("Failed to send mail: {}", e);
         }
         tokio::time::sleep(std::time::Duration::from_secs(send_duration)).await;
@@ -70,22 +102,67 @@ async fn process_mails(send_duration: u64) -> Result<()> {
     Ok(())
 }
 
-pub async fn send_reset_mail(mail: &str, id: &str) -> Result<()> {
+pub async fn send_reset_mail(tenant: &str, mail: &str, id: &str) -> Result<()> {
     info!("send reset mail");
+    send_link_mail(Purpose::Reset, tenant, mail, id).await
+}
+
+pub async fn send_unlock_mail(tenant: &str, mail: &str, id: &str) -> Result<()> {
+    info!("send unlock mail");
+    send_link_mail(Purpose::Unlock, tenant, mail, id).await
+}
+
+async fn send_link_mail(purpose: Purpose, tenant: &str, mail: &str, id: &str) -> Result<()> {
+    let (link, ddl) = build_link(purpose, tenant, mail, id)?;
+    let config = get_config();
+    let lang = get_student_lang(tenant, id)
+        .and_then(|lang| config.catalogues.pick(&lang).map(str::to_string))
+        .unwrap_or_else(|| config.locale.default.clone());
+    let tenant = config
+        .tenant(tenant)
+        .with_context(|| format!("unknown tenant: {:?}", tenant))?;
+    let messages = Messages::new(
+        &lang,
+        vec![
+            ("course", tenant.course().to_string()),
+            ("id", id.to_string()),
+            ("link", link),
+            ("deadline", ddl),
+        ],
+    );
+    // configured texts only replace those of the default language
+    let (subject, body) = match tenant.mails(&config) {
+        Some(mails) if lang == config.locale.default => {
+            let (subject, body) = match purpose {
+                Purpose::Reset => (&mails.reset_subject, &mails.reset_body),
+                Purpose::Unlock => (&mails.unlock_subject, &mails.unlock_body),
+            };
+            (fill(subject, messages.vars()), fill(body, messages.vars()))
+        }
+        _ => {
+            let key = |name: &str| format!("mail.{}_{}", purpose.route(), name);
+            (messages.text(&key("subject")), messages.text(&key("body")))
+        }
+    };
+    if let Err(e) = send_mail(mail, &subject, &body).await {
+        metrics::MAIL_SEND_FAILURES.inc();
+        return Err(e);
+    }
+    metrics::MAILS_SENT.inc();
+    Ok(())
+}
+
+// (link, deadline of the link)
+fn build_link(purpose: Purpose, tenant: &str, mail: &str, id: &str) -> Result<(String, String)> {
     let link = {
-        let domain = &get_config().server.domain;
-        let port = get_config().server.port;
-        let payload = build_payload(id)?;
-        format!("http://{}:{}/reset/{}", domain, port, payload)
+        let payload = build_payload(purpose, tenant, id, mail)?;
+        get_config()
+            .server
+            .link(&format!("/{}/{}", purpose.route(), payload))
     };
     let ddl = {
-        let ddl = Local::now() + Duration::seconds(get_config().payload.oudate_secounds as _);
+        let ddl = Local::now() + Duration::seconds(get_config().payload.outdate_seconds as _);
         ddl.to_rfc3339()
     };
-    let text = format!(
-        "请点击以下链接重置密码，链接在 {} 前有效 \n\n {}",
-        ddl, link
-    );
-    send_mail(mail, "重置密码", &text).await?;
-    Ok(())
+    Ok((link, ddl))
 }
@@ -0,0 +1,184 @@
+use crate::config::get_config;
+use std::{
+    fmt::Write,
+    sync::atomic::{AtomicBool, AtomicU64, Ordering},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+pub static MAILS_FETCHED: Counter = Counter::new(
+    "tenzin_mails_fetched_total",
+    "Unread mails fetched over IMAP.",
+);
+pub static MAIL_PARSE_FAILURES: Counter = Counter::new(
+    "tenzin_mail_parse_failures_total",
+    "Fetched mails whose subject or sender could not be parsed.",
+);
+pub static MAILS_REJECTED: Counter = Counter::new(
+    "tenzin_mails_rejected_total",
+    "Requests not sent from the email registered for the student.",
+);
+pub static MAILS_SENT: Counter = Counter::new("tenzin_mails_sent_total", "Link mails sent.");
+pub static MAIL_SEND_FAILURES: Counter = Counter::new(
+    "tenzin_mail_send_failures_total",
+    "Link mails that failed to send.",
+);
+pub static RESETS: Counter = Counter::new("tenzin_resets_total", "Passwords reset.");
+
+pub static IMAP_POLL_LAST_SUCCESS: Timestamp = Timestamp::new(
+    "tenzin_imap_poll_last_success_timestamp_seconds",
+    "Last time the mail worker polled IMAP without errors.",
+);
+pub static STUDENT_WALK_LAST_SUCCESS: Timestamp = Timestamp::new(
+    "tenzin_student_walk_last_success_timestamp_seconds",
+    "Last time the student directories were walked without errors.",
+);
+
+const COUNTERS: &[&Counter] = &[
+    &MAILS_FETCHED,
+    &MAIL_PARSE_FAILURES,
+    &MAILS_REJECTED,
+    &MAILS_SENT,
+    &MAIL_SEND_FAILURES,
+    &RESETS,
+];
+const TIMESTAMPS: &[&Timestamp] = &[&IMAP_POLL_LAST_SUCCESS, &STUDENT_WALK_LAST_SUCCESS];
+
+// whether this process polls IMAP, only then its polls count for readiness
+static MAIL_WORKER_RUNNING: AtomicBool = AtomicBool::new(false);
+
+pub struct Counter {
+    name: &'static str,
+    help: &'static str,
+    value: AtomicU64,
+}
+
+impl Counter {
+    const fn new(name: &'static str, help: &'static str) -> Self {
+        Self {
+            name,
+            help,
+            value: AtomicU64::new(0),
+        }
+    }
+
+    pub fn inc(&self) {
+        self.add(1);
+    }
+
+    pub fn add(&self, n: u64) {
+        self.value.fetch_add(n, Ordering::Relaxed);
+    }
+
+    pub fn get(&self) -> u64 {
+        self.value.load(Ordering::Relaxed)
+    }
+}
+
+/// Seconds since the epoch, 0 if it never happened.
+pub struct Timestamp {
+    name: &'static str,
+    help: &'static str,
+    value: AtomicU64,
+}
+
+impl Timestamp {
+    const fn new(name: &'static str, help: &'static str) -> Self {
+        Self {
+            name,
+            help,
+            value: AtomicU64::new(0),
+        }
+    }
+
+    pub fn set_now(&self) {
+        self.value.store(now(), Ordering::Relaxed);
+    }
+
+    pub fn get(&self) -> Option<u64> {
+        match self.value.load(Ordering::Relaxed) {
+            0 => None,
+            t => Some(t),
+        }
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs())
+}
+
+pub fn set_mail_worker_running() {
+    MAIL_WORKER_RUNNING.store(true, Ordering::Relaxed);
+}
+
+/// All metrics in the Prometheus text format.
+pub fn render() -> String {
+    let mut text = String::new();
+    for counter in COUNTERS {
+        let _ = writeln!(text, "# HELP {} {}", counter.name, counter.help);
+        let _ = writeln!(text, "# TYPE {} counter", counter.name);
+        let _ = writeln!(text, "{} {}", counter.name, counter.get());
+    }
+    for timestamp in TIMESTAMPS {
+        let _ = writeln!(text, "# HELP {} {}", timestamp.name, timestamp.help);
+        let _ = writeln!(text, "# TYPE {} gauge", timestamp.name);
+        let _ = writeln!(text, "{} {}", timestamp.name, timestamp.get().unwrap_or(0));
+    }
+    text
+}
+
+/// Why this process isn't ready, empty if it is.
+///
+/// The student directories must have been walked, and IMAP polled if this
+/// process runs the mail worker, each no more than three rounds ago.
+pub fn readiness_problems() -> Vec<String> {
+    let config = get_config();
+    let mut checks = vec![(
+        "student directories walked",
+        &STUDENT_WALK_LAST_SUCCESS,
+        config.student.walk_duration,
+    )];
+    if MAIL_WORKER_RUNNING.load(Ordering::Relaxed) {
+        checks.push((
+            "IMAP polled",
+            &IMAP_POLL_LAST_SUCCESS,
+            config.mail.check_duration,
+        ));
+    }
+    checks
+        .into_iter()
+        .filter_map(|(what, last, interval)| problem(what, last.get(), interval, now()))
+        .collect()
+}
+
+// also allows a minute for the round itself
+fn problem(what: &str, last: Option<u64>, interval: u64, now: u64) -> Option<String> {
+    match last {
+        None => Some(format!("{}: never", what)),
+        Some(last) if now.saturating_sub(last) > 3 * interval + 60 => {
+            Some(format!("{}: last {} seconds ago", what, now - last))
+        }
+        Some(_) => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_metrics() {
+        RESETS.inc();
+        let text = render();
+        assert!(text.contains("# TYPE tenzin_resets_total counter\n"));
+        assert!(text
+            .lines()
+            .any(|l| l.starts_with("tenzin_resets_total ") && !l.ends_with(" 0")));
+        assert!(text.contains("\ntenzin_imap_poll_last_success_timestamp_seconds "));
+
+        assert!(problem("x", None, 60, 1000).is_some());
+        assert!(problem("x", Some(1000), 60, 1240).is_none());
+        assert!(problem("x", Some(1000), 60, 1241).is_some());
+    }
+}
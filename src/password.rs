@@ -0,0 +1,78 @@
+use crate::config::PasswordPolicy;
+use anyhow::{bail, Result};
+use hmac::{Hmac, Mac, NewMac};
+use rand::{seq::SliceRandom, Rng, SeedableRng};
+use rand_chacha::ChaCha20Rng;
+use sha2::Sha256;
+
+/// Generates the password a reset sets for `id`.
+pub fn generate_password(policy: &PasswordPolicy, id: &str) -> Result<String> {
+    match policy {
+        PasswordPolicy::Template { template } => Ok(template.replace("{id}", id)),
+        PasswordPolicy::Random { length, alphabet } => {
+            sample(&mut rand::thread_rng(), alphabet, *length)
+        }
+        // the same id always gets the same password, so staff knowing the
+        // secret can tell it to a student
+        PasswordPolicy::Hmac {
+            secret,
+            length,
+            alphabet,
+        } => {
+            let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes())
+                .map_err(|e| anyhow::anyhow!("invalid hmac secret: {}", e))?;
+            mac.update(id.as_bytes());
+            let seed: [u8; 32] = mac.finalize().into_bytes().into();
+            sample(&mut ChaCha20Rng::from_seed(seed), alphabet, *length)
+        }
+    }
+}
+
+fn sample(rng: &mut impl Rng, alphabet: &str, length: usize) -> Result<String> {
+    let alphabet: Vec<char> = alphabet.chars().collect();
+    if alphabet.is_empty() || length == 0 {
+        bail!("password alphabet and length must not be empty");
+    }
+    Ok((0..length)
+        .map(|_| *alphabet.choose(rng).expect("alphabet is not empty"))
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_generate_password() -> Result<()> {
+        let template = PasswordPolicy::Template {
+            template: "bupt{id}".to_string(),
+        };
+        assert_eq!(generate_password(&template, "233")?, "bupt233");
+        let default = generate_password(&PasswordPolicy::default(), "233")?;
+        assert_eq!(default.len(), 12);
+        assert_ne!(
+            default,
+            generate_password(&PasswordPolicy::default(), "233")?
+        );
+
+        let random = PasswordPolicy::Random {
+            length: 16,
+            alphabet: "ab".to_string(),
+        };
+        let password = generate_password(&random, "233")?;
+        assert_eq!(password.len(), 16);
+        assert!(password.chars().all(|c| c == 'a' || c == 'b'));
+
+        let hmac = |secret: &str| PasswordPolicy::Hmac {
+            secret: secret.to_string(),
+            length: 12,
+            alphabet: "0123456789abcdef".to_string(),
+        };
+        let password = generate_password(&hmac("s1"), "233")?;
+        assert_eq!(password.len(), 12);
+        assert_eq!(password, generate_password(&hmac("s1"), "233")?);
+        assert_ne!(password, generate_password(&hmac("s1"), "234")?);
+        assert_ne!(password, generate_password(&hmac("s2"), "233")?);
+        Ok(())
+    }
+}
@@ -1,52 +1,321 @@
-use anyhow::Result;
+use anyhow::{Context, Result};
+use chacha20poly1305::{
+    aead::{Aead, KeyInit},
+    XChaCha20Poly1305, XNonce,
+};
 use chrono::Utc;
-use ed25519_zebra::VerificationKey;
+use ed25519_zebra::SigningKey;
+use hmac::{Hmac, Mac, NewMac};
+use rand::Rng;
 use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fmt;
 
-use crate::config::get_config;
+use crate::config::{get_config, PayloadMode};
 
-#[derive(Debug, Serialize, Deserialize)]
+/// Version of the payload format produced by [`build_payload`].
+///
+/// Payloads of this version are prefixed with `v3.` in the URL, or `e3.` if
+/// encrypted. Version 2 (`v2.`, `e2.`) had no tenant, and version 1 (no
+/// prefix) is the format before versions, of reset links carrying only the id
+/// and the time; both belong to the first tenant.
+pub const PAYLOAD_VERSION: u8 = 3;
+const PAYLOAD_PREFIX: &str = "v3.";
+const ENCRYPTED_PAYLOAD_PREFIX: &str = "e3.";
+const V2_PAYLOAD_PREFIX: &str = "v2.";
+const V2_ENCRYPTED_PAYLOAD_PREFIX: &str = "e2.";
+const NONCE_LEN: usize = 24;
+// Tolerated clock difference between the mail worker and the web server.
+const MAX_CLOCK_SKEW: i64 = 60;
+
+/// What a payload authorizes, a payload is only accepted by routes of its purpose.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Purpose {
+    Reset,
+    Unlock,
+}
+
+impl Purpose {
+    /// Route of the web page redeeming payloads of this purpose.
+    pub fn route(&self) -> &'static str {
+        match self {
+            Self::Reset => "reset",
+            Self::Unlock => "unlock",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Request {
+    pub version: u8,
+    /// Name of the tenant the student belongs to.
+    pub tenant: String,
+    pub purpose: Purpose,
     pub id: String,
     pub timestamp: i64,
+    /// Random id of this link, used to redeem it only once.
+    pub token: String,
+    /// Hash of the email this link was sent to, see [`email_hash`].
+    pub email_hash: String,
+}
+
+// Request of version 2 payloads.
+#[derive(Debug, Serialize, Deserialize)]
+struct V2Request {
+    version: u8,
+    purpose: Purpose,
+    id: String,
+    timestamp: i64,
+    token: String,
+    email_hash: String,
+}
+
+impl From<V2Request> for Request {
+    fn from(req: V2Request) -> Self {
+        Self {
+            version: req.version,
+            tenant: String::new(),
+            purpose: req.purpose,
+            id: req.id,
+            timestamp: req.timestamp,
+            token: req.token,
+            email_hash: req.email_hash,
+        }
+    }
+}
+
+// Request of version 1 payloads.
+#[derive(Debug, Serialize, Deserialize)]
+struct LegacyRequest {
+    id: String,
+    timestamp: i64,
+}
+
+// Version 1 payloads, signed by the key now configured with `sign.key_id`.
+#[derive(Debug, Serialize, Deserialize)]
+struct LegacyPayload {
+    request: Vec<u8>,
+    sign: Vec<u8>,
+}
+
+impl LegacyRequest {
+    // without a token of its own, the link is redeemed once by its signature,
+    // and without an email hash only the student's existence is checked
+    fn into_request(self, sign: &[u8]) -> Request {
+        Request {
+            version: 1,
+            tenant: String::new(),
+            purpose: Purpose::Reset,
+            id: self.id,
+            timestamp: self.timestamp,
+            token: base64_url::encode(&Sha256::digest(sign)),
+            email_hash: String::new(),
+        }
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Payload {
+    /// Id of the key which signed `request`.
+    pub key_id: String,
     pub request: Vec<u8>,
     pub sign: Vec<u8>,
 }
 
-pub fn build_payload(id: &str) -> Result<String> {
-    let key = &get_config().sign.key;
+/// HMAC of `email` keyed with `sign.hash_key`, so a link doesn't tell
+/// whether it was sent to a guessed address.
+pub fn email_hash(key: &[u8; 32], email: &str) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC takes keys of any size");
+    mac.update(email.as_bytes());
+    base64_url::encode(&mac.finalize().into_bytes())
+}
+
+pub fn build_payload(purpose: Purpose, tenant: &str, id: &str, email: &str) -> Result<String> {
+    let sign_config = &get_config().sign;
     let timestamp = Utc::now().timestamp();
+    let token: [u8; 16] = rand::thread_rng().gen();
     let request = Request {
+        version: PAYLOAD_VERSION,
+        tenant: tenant.to_string(),
+        purpose,
         id: id.to_string(),
         timestamp,
+        token: base64_url::encode(&token),
+        email_hash: email_hash(
+            &sign_config
+                .hash_key
+                .context("no sign.hash_key configured")?,
+            email,
+        ),
     };
-    let request = bincode::serialize(&request)?;
+    let key = sign_config
+        .key
+        .as_ref()
+        .context("no signing key configured, can't build payload")?;
+    let payload = sign_payload(key, &sign_config.key_id, &request)?;
+    let payload = match get_config().payload.mode {
+        PayloadMode::Signed => format!("{}{}", PAYLOAD_PREFIX, base64_url::encode(&payload)),
+        PayloadMode::Encrypted => format!(
+            "{}{}",
+            ENCRYPTED_PAYLOAD_PREFIX,
+            base64_url::encode(&encrypt_payload(&payload)?)
+        ),
+    };
+    Ok(urlencoding::encode(&payload).to_string())
+}
+
+fn sign_payload<T: Serialize>(key: &SigningKey, key_id: &str, request: &T) -> Result<Vec<u8>> {
+    let request = bincode::serialize(request)?;
     let sign: [u8; 64] = key.sign(&request).into();
     let payload = Payload {
+        key_id: key_id.to_string(),
         request,
         sign: sign.to_vec(),
     };
-    let payload = bincode::serialize(&payload)?;
-    let payload = base64_url::encode(&payload);
-    Ok(urlencoding::encode(&payload).to_string())
+    Ok(bincode::serialize(&payload)?)
+}
+
+fn cipher() -> Result<XChaCha20Poly1305> {
+    let key = get_config()
+        .sign
+        .encryption_key
+        .context("no encryption key configured")?;
+    Ok(XChaCha20Poly1305::new(&key.into()))
+}
+
+// nonce || ciphertext
+fn encrypt_payload(payload: &[u8]) -> Result<Vec<u8>> {
+    let nonce: [u8; NONCE_LEN] = rand::thread_rng().gen();
+    let ciphertext = cipher()?
+        .encrypt(XNonce::from_slice(&nonce), payload)
+        .map_err(|_| anyhow::anyhow!("failed to encrypt payload"))?;
+    Ok([&nonce[..], &ciphertext].concat())
+}
+
+fn decrypt_payload(payload: &[u8]) -> Result<Vec<u8>> {
+    if payload.len() < NONCE_LEN {
+        anyhow::bail!("encrypted payload is too short");
+    }
+    let (nonce, ciphertext) = payload.split_at(NONCE_LEN);
+    cipher()?
+        .decrypt(XNonce::from_slice(nonce), ciphertext)
+        .map_err(|_| anyhow::anyhow!("failed to decrypt payload"))
 }
 
-pub fn parse_payload(payload: &str) -> Result<Request> {
-    let key = &get_config().sign.key;
-    let payload = urlencoding::decode(payload)?;
-    let payload = base64_url::decode(payload.as_bytes())?;
-    let payload: Payload = bincode::deserialize(&payload)?;
-    let sign = ed25519_zebra::Signature::try_from(&payload.sign[..])?;
-    let vk = VerificationKey::from(key);
-    vk.verify(&sign, &payload.request)?;
-    let request: Request = bincode::deserialize(&payload.request)?;
+/// Why [`parse_payload`] rejected a payload.
+#[derive(Debug)]
+pub enum PayloadError {
+    /// Not a payload at all, e.g. truncated when copied.
+    Malformed(String),
+    /// Signed by an unknown key, forged or tampered with.
+    BadSignature(String),
+    /// Redeemed on the route of another purpose.
+    WrongPurpose { expected: Purpose, found: Purpose },
+    /// Older than `payload.outdate_seconds`.
+    Expired,
+    /// Of version 1, rejected by `payload.reject_legacy`.
+    LegacyRejected,
+    /// Issued in the future, the clocks of the worker and the server differ.
+    FromTheFuture,
+    /// Of a tenant no longer configured.
+    UnknownTenant(String),
+}
+
+impl fmt::Display for PayloadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Malformed(e) => write!(f, "malformed payload: {}", e),
+            Self::BadSignature(e) => write!(f, "bad signature: {}", e),
+            Self::WrongPurpose { expected, found } => {
+                write!(f, "payload is for {:?}, expected {:?}", found, expected)
+            }
+            Self::Expired => write!(f, "payload is outdated"),
+            Self::LegacyRejected => write!(f, "legacy payload is no longer accepted"),
+            Self::FromTheFuture => write!(f, "payload is from the future"),
+            Self::UnknownTenant(tenant) => write!(f, "unknown tenant: {:?}", tenant),
+        }
+    }
+}
+
+impl std::error::Error for PayloadError {}
+
+fn malformed(e: impl fmt::Display) -> PayloadError {
+    PayloadError::Malformed(e.to_string())
+}
+
+/// Verifies `payload` and checks it was issued for `purpose`.
+pub fn parse_payload(payload: &str, purpose: Purpose) -> Result<Request, PayloadError> {
+    let payload = urlencoding::decode(payload).map_err(malformed)?;
+    let decode = |payload: &str| base64_url::decode(payload).map_err(malformed);
+    let decrypt = |payload: &str| {
+        decrypt_payload(&decode(payload)?).map_err(|e| PayloadError::BadSignature(e.to_string()))
+    };
+    // links of both modes are accepted, so switching modes keeps outstanding links valid
+    let (version, payload) = if let Some(payload) = payload.strip_prefix(PAYLOAD_PREFIX) {
+        (PAYLOAD_VERSION, decode(payload)?)
+    } else if let Some(payload) = payload.strip_prefix(ENCRYPTED_PAYLOAD_PREFIX) {
+        (PAYLOAD_VERSION, decrypt(payload)?)
+    } else if let Some(payload) = payload.strip_prefix(V2_PAYLOAD_PREFIX) {
+        (2, decode(payload)?)
+    } else if let Some(payload) = payload.strip_prefix(V2_ENCRYPTED_PAYLOAD_PREFIX) {
+        (2, decrypt(payload)?)
+    } else {
+        (1, decode(&payload)?)
+    };
+    let legacy = version == 1;
+    if legacy && get_config().payload.reject_legacy {
+        return Err(PayloadError::LegacyRejected);
+    }
+    let payload: Payload = if legacy {
+        let payload: LegacyPayload = bincode::deserialize(&payload).map_err(malformed)?;
+        Payload {
+            key_id: get_config().sign.key_id.clone(),
+            request: payload.request,
+            sign: payload.sign,
+        }
+    } else {
+        bincode::deserialize(&payload).map_err(malformed)?
+    };
+    let sign = ed25519_zebra::Signature::try_from(&payload.sign[..]).map_err(malformed)?;
+    let vk = get_config()
+        .sign
+        .verification_key(&payload.key_id)
+        .ok_or_else(|| {
+            PayloadError::BadSignature(format!("unknown key id: {:?}", payload.key_id))
+        })?;
+    vk.verify(&sign, &payload.request)
+        .map_err(|e| PayloadError::BadSignature(e.to_string()))?;
+    let mut request: Request = match version {
+        1 => bincode::deserialize::<LegacyRequest>(&payload.request)
+            .map_err(malformed)?
+            .into_request(&payload.sign),
+        2 => bincode::deserialize::<V2Request>(&payload.request)
+            .map_err(malformed)?
+            .into(),
+        _ => bincode::deserialize(&payload.request).map_err(malformed)?,
+    };
+    if request.version != version {
+        return Err(malformed(format!(
+            "unsupported payload version: {}",
+            request.version
+        )));
+    }
+    let tenant = get_config()
+        .tenant(&request.tenant)
+        .ok_or_else(|| PayloadError::UnknownTenant(request.tenant.clone()))?;
+    request.tenant = tenant.name;
+    if request.purpose != purpose {
+        return Err(PayloadError::WrongPurpose {
+            expected: purpose,
+            found: request.purpose,
+        });
+    }
     let timestamp = Utc::now().timestamp();
-    if timestamp - request.timestamp > get_config().payload.oudate_secounds as i64 {
-        anyhow::bail!("payload is outdated");
+    if request.timestamp - timestamp > MAX_CLOCK_SKEW {
+        return Err(PayloadError::FromTheFuture);
+    }
+    if timestamp - request.timestamp > get_config().payload.outdate_seconds as i64 {
+        return Err(PayloadError::Expired);
     }
     Ok(request)
 }
@@ -54,20 +323,188 @@ pub fn parse_payload(payload: &str) -> Result<Request> {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::config::{set_config, Config, PayloadConfig};
+    use crate::config::{
+        set_config, Config, PayloadConfig, RetiredKey, SignConfig, DEFAULT_TENANT, TEST_CONFIG_LOCK,
+    };
+    use ed25519_zebra::VerificationKey;
+    use tokio::sync::MutexGuard;
 
-    #[test]
-    fn test_build_payload() -> Result<()> {
-        let config = Config {
+    // sets a config signing with key `2022b`, where `2022a` is the returned
+    // retired key
+    fn set_test_config() -> (MutexGuard<'static, ()>, SigningKey) {
+        let guard = TEST_CONFIG_LOCK.blocking_lock();
+        let retired = SigningKey::new(rand::thread_rng());
+        set_config(Config {
+            sign: SignConfig {
+                key_id: "2022b".to_string(),
+                encryption_key: Some(rand::thread_rng().gen()),
+                retired: vec![RetiredKey {
+                    id: "2022a".to_string(),
+                    public_key: VerificationKey::from(&retired),
+                }],
+                ..Default::default()
+            },
             payload: PayloadConfig {
-                oudate_secounds: 60,
+                outdate_seconds: 60,
+                ..Default::default()
             },
             ..Default::default()
-        };
-        set_config(config);
-        let payload = build_payload("test")?;
-        let request = parse_payload(&payload)?;
+        });
+        (guard, retired)
+    }
+
+    fn sign(key: &SigningKey, key_id: &str, request: &Request) -> Result<String> {
+        let payload = sign_payload(key, key_id, request)?;
+        Ok(format!(
+            "{}{}",
+            PAYLOAD_PREFIX,
+            base64_url::encode(&payload)
+        ))
+    }
+
+    fn test_email_hash(email: &str) -> String {
+        email_hash(&get_config().sign.hash_key.unwrap(), email)
+    }
+
+    fn test_request() -> Result<Request> {
+        let payload = build_payload(Purpose::Reset, "", "test", "test@bupt.edu.cn")?;
+        Ok(parse_payload(&payload, Purpose::Reset)?)
+    }
+
+    #[test]
+    fn test_build_payload() -> Result<()> {
+        let _config = set_test_config();
+        let request = test_request()?;
         assert_eq!(request.id, "test");
+        assert_eq!(request.tenant, DEFAULT_TENANT);
+        assert_eq!(request.email_hash, test_email_hash("test@bupt.edu.cn"));
+        assert_ne!(request.token, test_request()?.token);
+        Ok(())
+    }
+
+    #[test]
+    fn test_retired_keys() -> Result<()> {
+        let (_config, retired) = set_test_config();
+        let request = test_request()?;
+        let old = sign(&retired, "2022a", &request)?;
+        assert_eq!(parse_payload(&old, Purpose::Reset)?.id, "test");
+        let unknown = sign(&retired, "2021", &request)?;
+        assert!(matches!(
+            parse_payload(&unknown, Purpose::Reset),
+            Err(PayloadError::BadSignature(_))
+        ));
+        let forged = sign(&retired, "2022b", &request)?;
+        assert!(matches!(
+            parse_payload(&forged, Purpose::Reset),
+            Err(PayloadError::BadSignature(_))
+        ));
+        assert!(matches!(
+            parse_payload(&forged[..forged.len() / 2], Purpose::Reset),
+            Err(PayloadError::Malformed(_))
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn test_purpose() -> Result<()> {
+        let _config = set_test_config();
+        let unlock = build_payload(Purpose::Unlock, "", "test", "test@bupt.edu.cn")?;
+        assert!(matches!(
+            parse_payload(&unlock, Purpose::Reset),
+            Err(PayloadError::WrongPurpose { .. })
+        ));
+        assert!(parse_payload(&unlock, Purpose::Unlock).is_ok());
+        Ok(())
+    }
+
+    #[test]
+    fn test_timestamp() -> Result<()> {
+        let (_config, retired) = set_test_config();
+        let future = Request {
+            timestamp: Utc::now().timestamp() + 3600,
+            ..test_request()?
+        };
+        assert!(matches!(
+            parse_payload(&sign(&retired, "2022a", &future)?, Purpose::Reset),
+            Err(PayloadError::FromTheFuture)
+        ));
+        let expired = Request {
+            timestamp: Utc::now().timestamp() - 3600,
+            ..future
+        };
+        assert!(matches!(
+            parse_payload(&sign(&retired, "2022a", &expired)?, Purpose::Reset),
+            Err(PayloadError::Expired)
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn test_legacy_payload() -> Result<()> {
+        let _config = set_test_config();
+        // built the way links were before versions
+        let request = bincode::serialize(&LegacyRequest {
+            id: "legacy".to_string(),
+            timestamp: Utc::now().timestamp(),
+        })?;
+        let key = get_config().sign.key.unwrap();
+        let signature: [u8; 64] = key.sign(&request).into();
+        let legacy = bincode::serialize(&LegacyPayload {
+            request,
+            sign: signature.to_vec(),
+        })?;
+        let legacy = urlencoding::encode(&base64_url::encode(&legacy)).to_string();
+        let parsed = parse_payload(&legacy, Purpose::Reset)?;
+        assert_eq!((parsed.version, parsed.id.as_str()), (1, "legacy"));
+        assert_eq!(parsed.tenant, DEFAULT_TENANT);
+        assert_eq!(parsed.token, parse_payload(&legacy, Purpose::Reset)?.token);
+        assert!(matches!(
+            parse_payload(&legacy, Purpose::Unlock),
+            Err(PayloadError::WrongPurpose { .. })
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn test_v2_payload() -> Result<()> {
+        let (_config, retired) = set_test_config();
+        let v2 = V2Request {
+            version: 2,
+            purpose: Purpose::Unlock,
+            id: "v2".to_string(),
+            timestamp: Utc::now().timestamp(),
+            token: "token".to_string(),
+            email_hash: test_email_hash("test@bupt.edu.cn"),
+        };
+        let v2 = format!(
+            "{}{}",
+            V2_PAYLOAD_PREFIX,
+            base64_url::encode(&sign_payload(&retired, "2022a", &v2)?)
+        );
+        let v2 = parse_payload(&v2, Purpose::Unlock)?;
+        assert_eq!((v2.version, v2.tenant.as_str()), (2, DEFAULT_TENANT));
+        let elsewhere = Request {
+            version: PAYLOAD_VERSION,
+            tenant: "os".to_string(),
+            ..v2
+        };
+        assert!(matches!(
+            parse_payload(&sign(&retired, "2022a", &elsewhere)?, Purpose::Unlock),
+            Err(PayloadError::UnknownTenant(_))
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn test_encrypt_payload() -> Result<()> {
+        let (_config, retired) = set_test_config();
+        let payload = sign_payload(&retired, "2022a", &test_request()?)?;
+        let encrypted = encrypt_payload(&payload)?;
+        assert!(!encrypted.windows(4).any(|w| w == b"test"));
+        assert_eq!(decrypt_payload(&encrypted)?, payload);
+        let mut tampered = encrypted;
+        tampered[NONCE_LEN] ^= 1;
+        assert!(decrypt_payload(&tampered).is_err());
         Ok(())
     }
 }
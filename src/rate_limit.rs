@@ -0,0 +1,211 @@
+use crate::{
+    config::{get_config, RateLimit},
+    timestamp_file::{sanitize_key, Reopening, TimestampFile},
+};
+use anyhow::Result;
+use chrono::Utc;
+use parking_lot::MappedMutexGuard;
+use std::{
+    collections::{HashMap, VecDeque},
+    path::Path,
+};
+use tracing::{debug, error, warn};
+
+// hits are pruned and the file compacted after this many hits
+const COMPACT_THRESHOLD: usize = 10_000;
+
+static RATE_LIMITER: Reopening<RateLimiter> = Reopening::new();
+
+fn open_configured_limiter() -> RateLimiter {
+    let config = get_config();
+    let path = &config.rate_limit.path;
+    let keep_seconds = config.max_rate_limit_window();
+    if path.is_empty() {
+        return RateLimiter::in_memory(keep_seconds);
+    }
+    match RateLimiter::open(path, keep_seconds) {
+        Ok(limiter) => limiter,
+        Err(e) => {
+            error!("Failed to open rate limits, counting in memory: {:#}", e);
+            RateLimiter::in_memory(keep_seconds)
+        }
+    }
+}
+
+/// Locks the limiter, reopening it first if `rate_limit.path` was reloaded.
+///
+/// A limiter which fell back to memory is kept until the path changes, so its
+/// counts aren't lost on every hit.
+fn rate_limiter() -> MappedMutexGuard<'static, RateLimiter> {
+    RATE_LIMITER
+        .lock(&get_config().rate_limit.path, || {
+            Ok(open_configured_limiter())
+        })
+        .expect("opening the rate limiter doesn't fail")
+}
+
+/// Counts a hit on `key`, returns false if it's over `limit`, see
+/// [`RateLimiter::check`].
+///
+/// Throttled hits are logged, failing to persist a hit is logged and the hit
+/// still counted in memory.
+pub async fn check_rate_limit(key: &str, limit: RateLimit) -> bool {
+    // the limiter blocks on file IO, keep it off the async runtime
+    let owned = key.to_string();
+    let (allowed, persisted) =
+        tokio::task::spawn_blocking(move || rate_limiter().check(&owned, limit))
+            .await
+            .unwrap_or_else(|e| (true, Err(e.into())));
+    if let Err(e) = persisted {
+        error!("Failed to persist rate limit hit of {}: {:#}", key, e);
+    }
+    if !allowed {
+        warn!(
+            "throttled {}: over {} per {} seconds",
+            key, limit.max, limit.window
+        );
+    }
+    allowed
+}
+
+/// Counts hits per key within sliding windows.
+///
+/// Hits are kept in a [`TimestampFile`], those older than the longest window
+/// are dropped when it's opened.
+#[derive(Debug)]
+pub struct RateLimiter {
+    file: Option<TimestampFile>,
+    // key -> timestamps of hits, oldest first
+    hits: HashMap<String, VecDeque<i64>>,
+    keep_seconds: u64,
+    appended: usize,
+}
+
+impl RateLimiter {
+    /// Keeps hits of the last `keep_seconds`, which must cover every window.
+    pub fn in_memory(keep_seconds: u64) -> Self {
+        Self {
+            file: None,
+            hits: HashMap::new(),
+            keep_seconds,
+            appended: 0,
+        }
+    }
+
+    /// Opens the file at `path`, keeping hits of the last `keep_seconds`.
+    pub fn open(path: impl AsRef<Path>, keep_seconds: u64) -> Result<Self> {
+        let file = TimestampFile::new(path);
+        let mut limiter = Self::in_memory(keep_seconds);
+        for (key, timestamp) in file.read()? {
+            limiter.hits.entry(key).or_default().push_back(timestamp);
+        }
+        limiter.hits.values_mut().for_each(|h| {
+            h.make_contiguous().sort_unstable();
+        });
+        limiter.file = Some(file);
+        limiter.prune(Utc::now().timestamp());
+        limiter.compact()?;
+        Ok(limiter)
+    }
+
+    /// Records a hit on `key` and returns true, or returns false without
+    /// recording it if `key` already had `limit.max` hits in the window.
+    ///
+    /// The hit is counted even if persisting it failed.
+    pub fn check(&mut self, key: &str, limit: RateLimit) -> (bool, Result<()>) {
+        self.check_at(key, limit, Utc::now().timestamp())
+    }
+
+    fn check_at(&mut self, key: &str, limit: RateLimit, now: i64) -> (bool, Result<()>) {
+        // keys end up in lines of the file
+        let key = sanitize_key(key);
+        let since = now - limit.window as i64;
+        let hits = self.hits.entry(key.clone()).or_default();
+        let recent = hits.iter().filter(|t| **t > since).count();
+        if recent >= limit.max as usize {
+            return (false, Ok(()));
+        }
+        hits.push_back(now);
+        (true, self.persist(&key, now))
+    }
+
+    fn persist(&mut self, key: &str, timestamp: i64) -> Result<()> {
+        self.appended += 1;
+        if self.appended >= COMPACT_THRESHOLD {
+            self.prune(timestamp);
+            return self.compact();
+        }
+        if let Some(file) = &self.file {
+            file.append(key, timestamp, false)?;
+        }
+        Ok(())
+    }
+
+    fn prune(&mut self, now: i64) {
+        // in memory, drop what's older than the longest window seen
+        let since = now - self.keep_seconds as i64;
+        for hits in self.hits.values_mut() {
+            while hits.front().is_some_and(|t| *t <= since) {
+                hits.pop_front();
+            }
+        }
+        self.hits.retain(|_, h| !h.is_empty());
+    }
+
+    fn compact(&mut self) -> Result<()> {
+        self.appended = 0;
+        if let Some(file) = &self.file {
+            let hits = self
+                .hits
+                .iter()
+                .flat_map(|(key, hits)| hits.iter().map(move |t| (key.as_str(), *t)));
+            file.rewrite(hits)?;
+            debug!("compacted rate limits: {} keys", self.hits.len());
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{set_config, Config, TEST_CONFIG_LOCK};
+
+    #[test]
+    fn test_rate_limiter() -> Result<()> {
+        let path = std::env::temp_dir().join(format!("tenzin-limits-{}", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let limit = RateLimit { max: 2, window: 60 };
+        let now = Utc::now().timestamp();
+        {
+            let mut limiter = RateLimiter::open(&path, 60)?;
+            assert!(limiter.check_at("a", limit, now - 100).0);
+            assert!(limiter.check_at("a", limit, now - 30).0);
+            assert!(limiter.check_at("a", limit, now - 20).0);
+            assert!(!limiter.check_at("a", limit, now - 10).0);
+            assert!(limiter.check_at("b\t\n", limit, now).0);
+        }
+        // the first hit left the window, the others survive a restart
+        let mut limiter = RateLimiter::open(&path, 60)?;
+        assert_eq!(limiter.hits.len(), 2);
+        assert!(!limiter.check_at("a", limit, now).0);
+        assert!(limiter.check_at("b", limit, now).0);
+        assert!(!limiter.check_at("b", limit, now).0);
+        assert!(limiter.check_at("a", limit, now + 60).0);
+        std::fs::remove_file(&path)?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_rate_limit_fallback() {
+        let _lock = TEST_CONFIG_LOCK.lock().await;
+        let mut config = Config::default();
+        config.rate_limit.path = "/nonexistent/tenzin/limits".to_string();
+        set_config(config);
+        assert!(RateLimiter::open(&get_config().rate_limit.path, 60).is_err());
+        // still counted in memory, across hits
+        let limit = RateLimit { max: 1, window: 60 };
+        assert!(check_rate_limit("fallback", limit).await);
+        assert!(!check_rate_limit("fallback", limit).await);
+    }
+}
@@ -1,91 +1,575 @@
 use crate::{
-    command::{reset_password_for, set_password_expire_for},
-    config::get_config,
-    payload::parse_payload,
+    command::{reset_password_for, set_password_expire_for, unlock_account_for},
+    config::{get_config, TenantConfig},
+    hook::{run_post_reset_hooks, HookFailure},
+    locale::Messages,
+    mail::{send_reset_mail, send_unlock_mail},
+    metrics,
+    payload::{parse_payload, PayloadError, Purpose, Request},
+    rate_limit::check_rate_limit,
+    student::{check_student_email_hash, get_student_email},
+    token_store::{claim_token, is_token_used},
 };
 use anyhow::Result;
 use askama::Template;
 use axum::{
-    extract,
-    http::StatusCode,
+    extract::{self, MatchedPath},
+    http::{self, header, StatusCode},
+    middleware::{self, Next},
     response::{Html, IntoResponse, Response},
     routing::get,
     Router,
 };
-use std::net::SocketAddr;
+use serde::Deserialize;
+use std::{net::IpAddr, path::Path};
+
+mod client_ip;
+mod error;
+mod health;
+mod lang;
+mod listen;
+
+pub use client_ip::{client_ip, ClientIp};
+pub use error::ServerError;
+pub use lang::{request_lang, Lang};
+pub use listen::reload_tls;
 
 pub async fn start_server() -> Result<()> {
-    let app = Router::new().route(
-        "/reset/:payload",
-        get(get_reset_handler).post(post_reset_handler),
-    );
-    let addr = SocketAddr::from(([0, 0, 0, 0], get_config().server.port));
-    tracing::debug!("listening on {}", addr);
-    axum::Server::bind(&addr)
-        .serve(app.into_make_service())
-        .await?;
-    Ok(())
-}
-
-async fn get_reset_handler(extract::Path(payload): extract::Path<String>) -> Response {
-    let f = |payload: String| -> anyhow::Result<ResetTemplate> {
-        let req = parse_payload(&payload)?;
-        let config = &get_config().server;
-        Ok(ResetTemplate {
-            id: req.id.clone(),
-            password: format!("bupt{}", req.id),
-            link: format!("http://{}:{}/reset/{}", config.domain, config.port, payload),
-        })
-    };
-    match f(payload) {
-        Ok(template) => HtmlTemplate(template).into_response(),
-        Err(e) => (
-            StatusCode::INTERNAL_SERVER_ERROR,
-            Html(format!(
-                "Invalid payload: {}, contact name1e5s@bupt.edu.cn for more info.",
-                e
-            )),
+    listen::serve(router()).await
+}
+
+/// Routes of the web server, under `server.path_prefix` except for the health
+/// checks.
+pub fn router() -> Router {
+    let mut routes = Router::new()
+        .route(
+            "/reset/:payload",
+            get(get_reset_handler).post(post_reset_handler),
         )
-            .into_response(),
+        .route(
+            "/unlock/:payload",
+            get(get_unlock_handler).post(post_unlock_handler),
+        )
+        .route_layer(middleware::from_fn(limit_link_visits));
+    let config = get_config();
+    if config.request_form.enabled {
+        if config.sign.key.is_some() {
+            routes = routes.route(
+                "/request",
+                get(get_request_handler).post(post_request_handler),
+            );
+        } else {
+            tracing::warn!("/request is disabled, it needs sign.key to send reset mails");
+        }
+    }
+    let routes = routes.route_layer(middleware::from_fn(log_request));
+    let prefix = &get_config().server.path_prefix;
+    let routes = if prefix.is_empty() {
+        routes
+    } else {
+        Router::new().nest(prefix, routes)
+    };
+    routes.merge(health::health_router())
+}
+
+async fn limit_link_visits<B>(req: http::Request<B>, next: Next<B>) -> Response {
+    let client = client_ip::request_client_ip(req.extensions(), req.headers());
+    let limit = get_config().rate_limit.per_ip;
+    if !check_rate_limit(&format!("link-ip:{}", client), limit).await {
+        return rate_limited_response(&request_lang(req.headers()));
     }
+    next.run(req).await
+}
+
+fn rate_limited_response(lang: &str) -> Response {
+    (
+        StatusCode::TOO_MANY_REQUESTS,
+        HtmlTemplate(RateLimitedTemplate {
+            t: Messages::new(lang, vec![("contact", default_contact())]),
+        }),
+    )
+        .into_response()
+}
+
+// payloads are left out, they redeem links
+async fn log_request<B>(req: http::Request<B>, next: Next<B>) -> Response {
+    let client = client_ip::request_client_ip(req.extensions(), req.headers());
+    let method = req.method().clone();
+    let route = req
+        .extensions()
+        .get::<MatchedPath>()
+        .map(|p| p.as_str().to_string())
+        .unwrap_or_default();
+    let response = next.run(req).await;
+    tracing::info!(%client, "{} {} {}", method, route, response.status());
+    response
 }
 
-async fn post_reset_handler(extract::Path(payload): extract::Path<String>) -> Response {
-    let f = |payload: String| -> anyhow::Result<ResetPostTemplate> {
-        let req = parse_payload(&payload)?;
-        reset_password_for(&req.id)?;
-        set_password_expire_for(&req.id)?;
-        Ok(ResetPostTemplate {
-            id: req.id.clone(),
-            password: format!("bupt{}", req.id),
-        })
+/// Parses `payload` and checks it was sent to the email registered now, or
+/// only that the student exists for version 1 payloads, which have no hash.
+fn verify_request(payload: &str, purpose: Purpose) -> Result<(Request, TenantConfig), ServerError> {
+    let req = parse_payload(payload, purpose)?;
+    let known = if req.version == 1 {
+        get_student_email(&req.tenant, &req.id).is_some()
+    } else {
+        check_student_email_hash(&req.tenant, &req.id, &req.email_hash)
     };
-    match f(payload) {
-        Ok(template) => HtmlTemplate(template).into_response(),
-        Err(e) => (
-            StatusCode::INTERNAL_SERVER_ERROR,
-            Html(format!(
-                "Invalid payload: {}, contact name1e5s@bupt.edu.cn for more info.",
-                e
-            )),
-        )
-            .into_response(),
+    if !known {
+        return Err(ServerError::UnknownStudent { id: req.id });
+    }
+    let tenant = get_config()
+        .tenant(&req.tenant)
+        .ok_or_else(|| PayloadError::UnknownTenant(req.tenant.clone()))?;
+    Ok((req, tenant))
+}
+
+// texts of the pages about `id` in `tenant`
+fn page_messages(lang: &str, tenant: &TenantConfig, id: &str) -> Messages {
+    Messages::new(
+        lang,
+        vec![
+            ("id", id.to_string()),
+            ("course", tenant.course().to_string()),
+            ("contact", tenant.contact.clone()),
+        ],
+    )
+}
+
+// contact shown on errors before the tenant is known
+fn default_contact() -> String {
+    get_config()
+        .tenant("")
+        .map(|t| t.contact)
+        .unwrap_or_default()
+}
+
+async fn get_reset_handler(
+    Lang(lang): Lang,
+    extract::Path(payload): extract::Path<String>,
+) -> Response {
+    let mut contact = default_contact();
+    match get_reset(payload, &lang, &mut contact).await {
+        Ok(response) => response,
+        Err(e) => e.into_page(&contact, &lang),
+    }
+}
+
+async fn get_reset(
+    payload: String,
+    lang: &str,
+    contact: &mut String,
+) -> Result<Response, ServerError> {
+    let (req, tenant) = verify_request(&payload, Purpose::Reset)?;
+    *contact = tenant.contact.clone();
+    if is_token_used(&req.token).await? {
+        return Ok(render_page(
+            &tenant,
+            UsedTemplate {
+                t: page_messages(lang, &tenant, &req.id),
+            },
+        ));
+    }
+    let config = &get_config().server;
+    Ok(render_page(
+        &tenant,
+        ResetTemplate {
+            link: config.link(&format!("/reset/{}", payload)),
+            t: page_messages(lang, &tenant, &req.id),
+        },
+    ))
+}
+
+async fn post_reset_handler(
+    Lang(lang): Lang,
+    extract::Path(payload): extract::Path<String>,
+) -> Response {
+    let mut contact = default_contact();
+    match post_reset(payload, &lang, &mut contact).await {
+        Ok(response) => response,
+        Err(e) => e.into_page(&contact, &lang),
+    }
+}
+
+async fn post_reset(
+    payload: String,
+    lang: &str,
+    contact: &mut String,
+) -> Result<Response, ServerError> {
+    let (req, tenant) = verify_request(&payload, Purpose::Reset)?;
+    *contact = tenant.contact.clone();
+    let claim = match claim_token(&req.token, req.timestamp).await? {
+        Some(claim) => claim,
+        None => {
+            tracing::info!("reset link for {} already used", req.id);
+            return Ok(render_page(
+                &tenant,
+                UsedTemplate {
+                    t: page_messages(lang, &tenant, &req.id),
+                },
+            ));
+        }
+    };
+    let policy = tenant.password_policy(&get_config()).clone();
+    let result = match reset_password_for(&req.id, &policy).await {
+        Ok(password) => set_password_expire_for(&req.id).await.map(|_| password),
+        Err(e) => Err(e),
+    };
+    let password = match result {
+        Ok(password) => password,
+        Err(e) => {
+            claim.release().await;
+            let e = e.context(format!("Failed to reset password for {}", req.id));
+            return Err(ServerError::backend(&req.id, e));
+        }
+    };
+    metrics::RESETS.inc();
+    let hook_failures = run_post_reset_hooks(&req.id).await;
+    // the password is shown only once, keep it out of any cache
+    Ok((
+        [(header::CACHE_CONTROL, "no-store")],
+        render_page(
+            &tenant,
+            ResetPostTemplate {
+                hook_failures,
+                t: page_messages(lang, &tenant, &req.id).var("password", password),
+            },
+        ),
+    )
+        .into_response())
+}
+
+async fn get_unlock_handler(
+    Lang(lang): Lang,
+    extract::Path(payload): extract::Path<String>,
+) -> Response {
+    let mut contact = default_contact();
+    match get_unlock(payload, &lang, &mut contact).await {
+        Ok(response) => response,
+        Err(e) => e.into_page(&contact, &lang),
+    }
+}
+
+async fn get_unlock(
+    payload: String,
+    lang: &str,
+    contact: &mut String,
+) -> Result<Response, ServerError> {
+    let (req, tenant) = verify_request(&payload, Purpose::Unlock)?;
+    *contact = tenant.contact.clone();
+    if is_token_used(&req.token).await? {
+        return Ok(render_page(
+            &tenant,
+            UsedTemplate {
+                t: page_messages(lang, &tenant, &req.id),
+            },
+        ));
+    }
+    let config = &get_config().server;
+    Ok(render_page(
+        &tenant,
+        UnlockTemplate {
+            link: config.link(&format!("/unlock/{}", payload)),
+            t: page_messages(lang, &tenant, &req.id),
+        },
+    ))
+}
+
+async fn post_unlock_handler(
+    Lang(lang): Lang,
+    extract::Path(payload): extract::Path<String>,
+) -> Response {
+    let mut contact = default_contact();
+    match post_unlock(payload, &lang, &mut contact).await {
+        Ok(response) => response,
+        Err(e) => e.into_page(&contact, &lang),
+    }
+}
+
+async fn post_unlock(
+    payload: String,
+    lang: &str,
+    contact: &mut String,
+) -> Result<Response, ServerError> {
+    let (req, tenant) = verify_request(&payload, Purpose::Unlock)?;
+    *contact = tenant.contact.clone();
+    let claim = match claim_token(&req.token, req.timestamp).await? {
+        Some(claim) => claim,
+        None => {
+            tracing::info!("unlock link for {} already used", req.id);
+            return Ok(render_page(
+                &tenant,
+                UsedTemplate {
+                    t: page_messages(lang, &tenant, &req.id),
+                },
+            ));
+        }
+    };
+    if let Err(e) = unlock_account_for(&req.id).await {
+        claim.release().await;
+        let e = e.context(format!("Failed to unlock account {}", req.id));
+        return Err(ServerError::backend(&req.id, e));
+    }
+    Ok(render_page(
+        &tenant,
+        UnlockPostTemplate {
+            t: page_messages(lang, &tenant, &req.id),
+        },
+    ))
+}
+
+async fn get_request_handler(Lang(lang): Lang) -> Response {
+    let config = get_config();
+    let tenants = config
+        .tenants()
+        .iter()
+        .map(|t| (t.name.clone(), t.course().to_string()))
+        .collect();
+    HtmlTemplate(RequestTemplate {
+        link: config.server.link("/request"),
+        tenants,
+        t: Messages::new(&lang, vec![("contact", default_contact())]),
+    })
+    .into_response()
+}
+
+#[derive(Debug, Deserialize)]
+struct ResetRequestForm {
+    tenant: String,
+    id: String,
+    /// `reset` or `unlock`, a reset if missing.
+    #[serde(default)]
+    action: String,
+}
+
+// responds the same whether or not the id exists, and sends the mail in the
+// background so the response time doesn't tell either
+async fn post_request_handler(
+    ClientIp(ip): ClientIp,
+    Lang(lang): Lang,
+    extract::Form(form): extract::Form<ResetRequestForm>,
+) -> Response {
+    let config = get_config();
+    let limits = &config.request_form;
+    let id = form.id.trim().to_string();
+    if !check_rate_limit(&format!("form-ip:{}", ip), limits.per_ip).await
+        || !check_rate_limit(&format!("form-id:{}/{}", form.tenant, id), limits.per_id).await
+    {
+        return rate_limited_response(&lang);
+    }
+    let purpose = match form.action.as_str() {
+        "unlock" => Purpose::Unlock,
+        _ => Purpose::Reset,
+    };
+    // the lookup happens after the page is sent, so neither the page nor the
+    // time it takes tells whether the id exists
+    tokio::spawn(async move {
+        if let Err(e) = send_requested_mail(&form.tenant, &id, purpose, ip).await {
+            tracing::error!("Failed to send {} mail to {}: {:#}", purpose.route(), id, e);
+        }
+    });
+    HtmlTemplate(RequestPostTemplate {
+        t: Messages::new(&lang, vec![("contact", default_contact())]),
+    })
+    .into_response()
+}
+
+// Mails the link of `purpose` to `id` of `tenant` asked for on the web,
+// returns whether it did. Unknown and known ids both count against the
+// per-student limit, a throttled student gets no mail but the same page.
+async fn send_requested_mail(tenant: &str, id: &str, purpose: Purpose, ip: IpAddr) -> Result<bool> {
+    let action = purpose.route();
+    let student_key = format!("mail-student:{}/{}", tenant, id);
+    let allowed = check_rate_limit(&student_key, get_config().rate_limit.per_student).await;
+    let email = match get_student_email(tenant, id) {
+        Some(email) => email,
+        None => {
+            tracing::info!(client = %ip, "{} of unknown id {:?} requested", action, id);
+            return Ok(false);
+        }
+    };
+    if !allowed {
+        return Ok(false);
+    }
+    tracing::info!(client = %ip, "{} of {} requested on the web", action, id);
+    match purpose {
+        Purpose::Reset => send_reset_mail(tenant, &email, id).await?,
+        Purpose::Unlock => send_unlock_mail(tenant, &email, id).await?,
+    }
+    Ok(true)
+}
+
+#[derive(Template)]
+#[template(path = "request.html")]
+struct RequestTemplate {
+    link: String,
+    // (name, course)
+    tenants: Vec<(String, String)>,
+    t: Messages,
+}
+
+#[derive(Template)]
+#[template(path = "request_post.html")]
+struct RequestPostTemplate {
+    t: Messages,
+}
+
+#[derive(Template)]
+#[template(path = "rate_limited.html")]
+struct RateLimitedTemplate {
+    t: Messages,
+}
+
+/// A page a tenant may replace with `<NAME>.<lang>.html` or `<NAME>.html` in
+/// its `templates` directory, where `{{ <var> }}` is replaced by the
+/// HTML-escaped value of the var.
+trait Page: Template {
+    const NAME: &'static str;
+
+    fn messages(&self) -> &Messages;
+
+    /// Vars besides those of [`Page::messages`].
+    fn extra_vars(&self) -> Vec<(&'static str, String)> {
+        Vec::new()
     }
 }
 
 #[derive(Template)]
 #[template(path = "reset.html")]
 struct ResetTemplate {
-    id: String,
-    password: String,
     link: String,
+    t: Messages,
+}
+
+impl Page for ResetTemplate {
+    const NAME: &'static str = "reset";
+
+    fn messages(&self) -> &Messages {
+        &self.t
+    }
+
+    fn extra_vars(&self) -> Vec<(&'static str, String)> {
+        vec![("link", self.link.clone())]
+    }
 }
 
 #[derive(Template)]
 #[template(path = "reset_post.html")]
 struct ResetPostTemplate {
-    id: String,
-    password: String,
+    hook_failures: Vec<HookFailure>,
+    // with the password
+    t: Messages,
+}
+
+impl Page for ResetPostTemplate {
+    const NAME: &'static str = "reset_post";
+
+    fn messages(&self) -> &Messages {
+        &self.t
+    }
+
+    fn extra_vars(&self) -> Vec<(&'static str, String)> {
+        let hook_failures = self
+            .hook_failures
+            .iter()
+            .map(|f| f.hook)
+            .collect::<Vec<_>>()
+            .join("\n");
+        vec![("hook_failures", hook_failures)]
+    }
+}
+
+#[derive(Template)]
+#[template(path = "unlock.html")]
+struct UnlockTemplate {
+    link: String,
+    t: Messages,
+}
+
+impl Page for UnlockTemplate {
+    const NAME: &'static str = "unlock";
+
+    fn messages(&self) -> &Messages {
+        &self.t
+    }
+
+    fn extra_vars(&self) -> Vec<(&'static str, String)> {
+        vec![("link", self.link.clone())]
+    }
+}
+
+#[derive(Template)]
+#[template(path = "unlock_post.html")]
+struct UnlockPostTemplate {
+    t: Messages,
+}
+
+impl Page for UnlockPostTemplate {
+    const NAME: &'static str = "unlock_post";
+
+    fn messages(&self) -> &Messages {
+        &self.t
+    }
+}
+
+#[derive(Template)]
+#[template(path = "used.html")]
+struct UsedTemplate {
+    t: Messages,
+}
+
+impl Page for UsedTemplate {
+    const NAME: &'static str = "used";
+
+    fn messages(&self) -> &Messages {
+        &self.t
+    }
+}
+
+/// Renders the override of `page` in the templates of `tenant`, or the
+/// built-in page if there is none.
+fn render_page<T: Page>(tenant: &TenantConfig, page: T) -> Response {
+    if !tenant.templates.is_empty() {
+        let lang = page.messages().lang();
+        for name in [
+            format!("{}.{}.html", T::NAME, lang),
+            format!("{}.html", T::NAME),
+        ] {
+            let path = Path::new(&tenant.templates).join(name);
+            match std::fs::read_to_string(&path) {
+                Ok(html) => {
+                    let mut vars = page.messages().vars().to_vec();
+                    vars.extend(page.extra_vars());
+                    return Html(render_override(&html, &vars)).into_response();
+                }
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+                Err(e) => tracing::error!("Failed to read {}: {}", path.display(), e),
+            }
+        }
+    }
+    HtmlTemplate(page).into_response()
+}
+
+fn render_override(html: &str, vars: &[(&str, String)]) -> String {
+    let mut html = html.to_string();
+    for (name, value) in vars {
+        html = html.replace(&format!("{{{{ {} }}}}", name), &escape_html(value));
+    }
+    html
+}
+
+fn escape_html(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#x27;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
 }
 
 struct HtmlTemplate<T>(T);
@@ -97,11 +581,177 @@ where
     fn into_response(self) -> Response {
         match self.0.render() {
             Ok(html) => Html(html).into_response(),
-            Err(err) => (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                format!("Failed to render template. Error: {}", err),
-            )
-                .into_response(),
+            Err(err) => {
+                tracing::error!("Failed to render template: {}", err);
+                (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    "Internal server error, please try again later.",
+                )
+                    .into_response()
+            }
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        config::{
+            set_config, BackendConfig, Config, GuardConfig, PasswordConfig, PayloadConfig,
+            RateLimit, StudentConfig, DEFAULT_TENANT, TEST_CONFIG_LOCK,
+        },
+        payload::build_payload,
+        student::walk_student_dir,
+    };
+    use hyper::{service::Service, Body};
+    use std::path::PathBuf;
+    use tokio::sync::MutexGuard;
+
+    const ID: &str = "2018000";
+
+    // a config resetting with the dry-run backend, whose students live in the
+    // returned directory
+    async fn set_test_config(name: &str) -> Result<(MutexGuard<'static, ()>, PathBuf)> {
+        let guard = TEST_CONFIG_LOCK.lock().await;
+        let home = std::env::temp_dir().join(format!("tenzin-{}-{}", name, std::process::id()));
+        std::fs::create_dir_all(home.join(ID))?;
+        set_config(Config {
+            payload: PayloadConfig {
+                outdate_seconds: 60,
+                ..Default::default()
+            },
+            student: StudentConfig {
+                home_prefix: home.to_string_lossy().to_string(),
+                walk_duration: 60,
+            },
+            password: PasswordConfig {
+                backends: vec![BackendConfig::DryRun],
+                ..Default::default()
+            },
+            guard: GuardConfig {
+                require_system_user: false,
+                ..Default::default()
+            },
+            ..Default::default()
+        });
+        Ok((guard, home))
+    }
+
+    // registers `email` for `ID`, as its `.tenzin` file would
+    fn register(home: &Path, email: &str) -> Result<()> {
+        std::fs::write(home.join(ID).join(".tenzin"), email)?;
+        walk_student_dir()
+    }
+
+    async fn send(method: &str, uri: &str, form: &str) -> Result<(StatusCode, String)> {
+        let req = http::Request::builder()
+            .method(method)
+            .uri(uri)
+            .header(header::CONTENT_TYPE, "application/x-www-form-urlencoded")
+            .body(Body::from(form.to_string()))?;
+        let response = router().call(req).await?;
+        let status = response.status();
+        let body = hyper::body::to_bytes(response.into_body()).await?;
+        Ok((status, String::from_utf8(body.to_vec())?))
+    }
+
+    // text `key` of pages in the default language
+    fn text(key: &str) -> String {
+        Messages::new(&get_config().locale.default, Vec::new()).text(key)
+    }
+
+    #[tokio::test]
+    async fn test_reset_link_works_once() -> Result<()> {
+        let (_config, home) = set_test_config("once").await?;
+        register(&home, "a@bupt.edu.cn")?;
+        let payload = build_payload(Purpose::Reset, "", ID, "a@bupt.edu.cn")?;
+        let uri = format!("/reset/{}", payload);
+
+        let (status, page) = send("GET", &uri, "").await?;
+        assert_eq!(status, StatusCode::OK);
+        assert!(!page.contains(&text("used.once")));
+        let (status, page) = send("POST", &uri, "").await?;
+        assert_eq!(status, StatusCode::OK);
+        assert!(page.contains(&text("reset_post.shown_once")));
+        for method in ["POST", "GET"] {
+            let (status, page) = send(method, &uri, "").await?;
+            assert_eq!(status, StatusCode::OK);
+            assert!(page.contains(&text("used.once")));
+            assert!(!page.contains(&text("reset_post.shown_once")));
+        }
+        std::fs::remove_dir_all(&home)?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_request_form_hides_unknown_ids() -> Result<()> {
+        let (_config, home) = set_test_config("request").await?;
+        register(&home, "a@bupt.edu.cn")?;
+        let form = |id: &str| format!("tenant={}&id={}", DEFAULT_TENANT, id);
+        let known = send("POST", "/request", &form(ID)).await?;
+        let unknown = send("POST", "/request", &form("2018404")).await?;
+        assert_eq!(known.0, StatusCode::OK);
+        assert_eq!(known, unknown);
+        let unlock = send("POST", "/request", &format!("{}&action=unlock", form(ID))).await?;
+        assert_eq!(unlock, unknown);
+        std::fs::remove_dir_all(&home)?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_requested_mail_counts_unknown_ids() -> Result<()> {
+        let (_config, home) = set_test_config("requested-mail").await?;
+        // an id of its own, other tests send requests for `ID`
+        let id = "2018001";
+        std::fs::create_dir_all(home.join(id))?;
+        std::fs::write(home.join(id).join(".tenzin"), "a@bupt.edu.cn")?;
+        walk_student_dir()?;
+        let mut config = (*get_config()).clone();
+        config.rate_limit.per_student = RateLimit { max: 1, window: 60 };
+        let limit = config.rate_limit.per_student;
+        set_config(config);
+        let ip = IpAddr::from([127, 0, 0, 1]);
+
+        // an unknown id goes through the per-student limit as a known one
+        assert!(!send_requested_mail(DEFAULT_TENANT, "2018404", Purpose::Reset, ip).await?);
+        let key = format!("mail-student:{}/2018404", DEFAULT_TENANT);
+        assert!(!check_rate_limit(&key, limit).await);
+
+        let key = format!("mail-student:{}/{}", DEFAULT_TENANT, id);
+        assert!(check_rate_limit(&key, limit).await);
+        assert!(!send_requested_mail(DEFAULT_TENANT, id, Purpose::Unlock, ip).await?);
+        std::fs::remove_dir_all(&home)?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_link_rejected_after_email_change() -> Result<()> {
+        let (_config, home) = set_test_config("email-change").await?;
+        register(&home, "a@bupt.edu.cn")?;
+        let payload = build_payload(Purpose::Reset, "", ID, "a@bupt.edu.cn")?;
+        let uri = format!("/reset/{}", payload);
+        assert_eq!(send("GET", &uri, "").await?.0, StatusCode::OK);
+
+        register(&home, "b@bupt.edu.cn")?;
+        for method in ["GET", "POST"] {
+            let (status, page) = send(method, &uri, "").await?;
+            assert_eq!(status, StatusCode::FORBIDDEN);
+            assert!(page.contains(&text("error.unknown_student")));
+        }
+        std::fs::remove_dir_all(&home)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_render_override() {
+        let vars = [
+            ("id", "<b>233</b>".to_string()),
+            ("contact", "a@b".to_string()),
+        ];
+        assert_eq!(
+            render_override("{{ id }} {{ contact }} {{ link }}", &vars),
+            "&lt;b&gt;233&lt;/b&gt; a@b {{ link }}"
+        );
+    }
+}
@@ -0,0 +1,129 @@
+use crate::config::get_config;
+use axum::{
+    async_trait,
+    extract::{ConnectInfo, FromRequest, RequestParts},
+    http::HeaderMap,
+};
+use std::{
+    convert::Infallible,
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+};
+
+const X_FORWARDED_FOR: &str = "x-forwarded-for";
+
+/// Address of the client, see [`client_ip`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ClientIp(pub IpAddr);
+
+#[async_trait]
+impl<B: Send> FromRequest<B> for ClientIp {
+    type Rejection = Infallible;
+
+    async fn from_request(req: &mut RequestParts<B>) -> Result<Self, Self::Rejection> {
+        Ok(Self(request_client_ip(req.extensions(), req.headers())))
+    }
+}
+
+/// Client address of a request served by [`super::start_server`].
+pub fn request_client_ip(extensions: &axum::http::Extensions, headers: &HeaderMap) -> IpAddr {
+    // there is none on a unix socket
+    let peer = extensions
+        .get::<ConnectInfo<SocketAddr>>()
+        .map(|c| c.0.ip());
+    client_ip(peer, headers, &get_config().server.trusted_proxies)
+}
+
+/// Address of the client behind `peer`, `None` for a unix socket peer.
+///
+/// `X-Forwarded-For` is only believed if `peer` is a trusted proxy, or a
+/// local process on the unix socket, the client is the rightmost address in
+/// it not added by a trusted proxy. IPv4 addresses mapped into IPv6, as seen
+/// on a dual-stack `[::]` bind, are compared and returned as IPv4.
+pub fn client_ip(peer: Option<IpAddr>, headers: &HeaderMap, trusted: &[IpAddr]) -> IpAddr {
+    let is_trusted = |ip: IpAddr| trusted.iter().any(|t| t.to_canonical() == ip);
+    let peer = match peer.map(|peer| peer.to_canonical()) {
+        Some(peer) if !is_trusted(peer) => return peer,
+        Some(peer) => peer,
+        None => IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+    };
+    let forwarded: Vec<&str> = headers
+        .get_all(X_FORWARDED_FOR)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .collect();
+    // walk back hop by hop, stopping at the first address we can't trust to
+    // have added the one before it
+    let mut client = peer;
+    for ip in forwarded.iter().rev() {
+        match ip.trim().parse::<IpAddr>() {
+            Ok(ip) => {
+                client = ip.to_canonical();
+                if !is_trusted(client) {
+                    break;
+                }
+            }
+            Err(_) => break,
+        }
+    }
+    client
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_client_ip() {
+        let ip = |s: &str| s.parse::<IpAddr>().unwrap();
+        let trusted = [ip("10.0.0.1"), ip("::1")];
+        let mut headers = HeaderMap::new();
+        assert_eq!(
+            client_ip(Some(ip("10.0.0.1")), &headers, &trusted),
+            ip("10.0.0.1")
+        );
+
+        headers.insert(X_FORWARDED_FOR, "1.1.1.1, 2.2.2.2, ::1".parse().unwrap());
+        assert_eq!(
+            client_ip(Some(ip("10.0.0.1")), &headers, &trusted),
+            ip("2.2.2.2")
+        );
+        // anyone else may send any header
+        assert_eq!(
+            client_ip(Some(ip("3.3.3.3")), &headers, &trusted),
+            ip("3.3.3.3")
+        );
+
+        headers.insert(X_FORWARDED_FOR, "::1".parse().unwrap());
+        assert_eq!(
+            client_ip(Some(ip("10.0.0.1")), &headers, &trusted),
+            ip("::1")
+        );
+        headers.insert(X_FORWARDED_FOR, "garbage".parse().unwrap());
+        assert_eq!(
+            client_ip(Some(ip("10.0.0.1")), &headers, &trusted),
+            ip("10.0.0.1")
+        );
+        headers.insert(X_FORWARDED_FOR, "1.1.1.1, garbage, ::1".parse().unwrap());
+        assert_eq!(
+            client_ip(Some(ip("10.0.0.1")), &headers, &trusted),
+            ip("::1")
+        );
+        headers.insert(X_FORWARDED_FOR, "1.1.1.1".parse().unwrap());
+        assert_eq!(client_ip(None, &headers, &trusted), ip("1.1.1.1"));
+
+        // IPv4 peers of a dual-stack bind
+        headers.insert(
+            X_FORWARDED_FOR,
+            "1.1.1.1, ::ffff:2.2.2.2, ::ffff:10.0.0.1".parse().unwrap(),
+        );
+        assert_eq!(
+            client_ip(Some(ip("::ffff:10.0.0.1")), &headers, &trusted),
+            ip("2.2.2.2")
+        );
+        assert_eq!(
+            client_ip(Some(ip("::ffff:3.3.3.3")), &headers, &trusted),
+            ip("3.3.3.3")
+        );
+    }
+}
@@ -0,0 +1,186 @@
+use super::HtmlTemplate;
+use crate::{config::get_config, guard::AccountRefused, locale::Messages, payload::PayloadError};
+use askama::Template;
+use axum::{
+    http::StatusCode,
+    response::{IntoResponse, Response},
+};
+use std::fmt;
+
+/// Why a page couldn't be served.
+///
+/// Students only see a friendly page for the kind of error, the details go
+/// to the log.
+#[derive(Debug)]
+pub enum ServerError {
+    Payload(PayloadError),
+    /// The student isn't registered, or no longer with the email the link
+    /// was sent to.
+    UnknownStudent {
+        id: String,
+    },
+    /// The guard refused to touch the account, only staff can help.
+    AccountRefused {
+        id: String,
+        reason: String,
+    },
+    /// Resetting or unlocking the account failed.
+    Backend(anyhow::Error),
+    Internal(anyhow::Error),
+}
+
+impl ServerError {
+    /// Failure of the backends changing `id`, told apart from a refusal by
+    /// the guard.
+    pub fn backend(id: &str, e: anyhow::Error) -> Self {
+        match e.downcast_ref::<AccountRefused>() {
+            Some(refused) => Self::AccountRefused {
+                id: id.to_string(),
+                reason: refused.0.clone(),
+            },
+            None => Self::Backend(e),
+        }
+    }
+
+    pub fn status(&self) -> StatusCode {
+        match self {
+            Self::Payload(PayloadError::Expired | PayloadError::LegacyRejected) => StatusCode::GONE,
+            Self::Payload(PayloadError::BadSignature(_))
+            | Self::UnknownStudent { .. }
+            | Self::AccountRefused { .. } => StatusCode::FORBIDDEN,
+            Self::Payload(PayloadError::UnknownTenant(_)) => StatusCode::NOT_FOUND,
+            Self::Payload(_) => StatusCode::BAD_REQUEST,
+            Self::Backend(_) | Self::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    // catalogue keys of (title, message)
+    fn page(&self) -> (&'static str, &'static str) {
+        match self {
+            Self::Payload(PayloadError::Expired | PayloadError::LegacyRejected) => {
+                ("error.expired_title", "error.expired")
+            }
+            Self::Payload(PayloadError::FromTheFuture) => ("error.future_title", "error.future"),
+            Self::Payload(PayloadError::WrongPurpose { .. }) => {
+                ("error.invalid_title", "error.wrong_purpose")
+            }
+            Self::Payload(PayloadError::BadSignature(_)) => {
+                ("error.invalid_title", "error.bad_signature")
+            }
+            Self::Payload(_) => ("error.invalid_title", "error.malformed"),
+            Self::UnknownStudent { .. } => ("error.unknown_student_title", "error.unknown_student"),
+            Self::AccountRefused { .. } => ("error.refused_title", "error.refused"),
+            Self::Backend(_) => ("error.backend_title", "error.backend"),
+            Self::Internal(_) => ("error.internal_title", "error.internal"),
+        }
+    }
+
+    /// Whether a new link would help.
+    fn offers_new_link(&self) -> bool {
+        matches!(
+            self,
+            Self::Payload(PayloadError::Expired | PayloadError::LegacyRejected)
+                | Self::UnknownStudent { .. }
+        )
+    }
+
+    /// Logs the details and renders the page shown instead, in `lang`.
+    pub fn into_page(self, contact: &str, lang: &str) -> Response {
+        match &self {
+            Self::Backend(_) | Self::Internal(_) => tracing::error!("{:#}", self),
+            _ => tracing::warn!("{:#}", self),
+        }
+        let config = get_config();
+        let request_link =
+            if self.offers_new_link() && config.request_form.enabled && config.sign.key.is_some() {
+                config.server.link("/request")
+            } else {
+                String::new()
+            };
+        let (title, message) = self.page();
+        (
+            self.status(),
+            HtmlTemplate(ErrorTemplate {
+                title,
+                message,
+                request_link,
+                t: Messages::new(lang, vec![("contact", contact.to_string())]),
+            }),
+        )
+            .into_response()
+    }
+}
+
+impl fmt::Display for ServerError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Payload(e) => write!(f, "{}", e),
+            Self::UnknownStudent { id } => {
+                write!(f, "{} is no longer registered with this email", id)
+            }
+            Self::AccountRefused { id, reason } => {
+                write!(f, "account {} refused: {}", id, reason)
+            }
+            Self::Backend(e) => write!(f, "backend failed: {:#}", e),
+            Self::Internal(e) => write!(f, "{:#}", e),
+        }
+    }
+}
+
+impl std::error::Error for ServerError {}
+
+impl From<PayloadError> for ServerError {
+    fn from(e: PayloadError) -> Self {
+        Self::Payload(e)
+    }
+}
+
+impl From<anyhow::Error> for ServerError {
+    fn from(e: anyhow::Error) -> Self {
+        Self::Internal(e)
+    }
+}
+
+#[derive(Template)]
+#[template(path = "error.html")]
+struct ErrorTemplate {
+    title: &'static str,
+    message: &'static str,
+    // empty if a new link can't be requested on the web
+    request_link: String,
+    t: Messages,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_status() {
+        let status = |e: PayloadError| ServerError::from(e).status();
+        assert_eq!(status(PayloadError::Expired), StatusCode::GONE);
+        assert_eq!(
+            status(PayloadError::BadSignature(String::new())),
+            StatusCode::FORBIDDEN
+        );
+        assert_eq!(
+            status(PayloadError::Malformed(String::new())),
+            StatusCode::BAD_REQUEST
+        );
+        let id = "233".to_string();
+        assert_eq!(
+            ServerError::UnknownStudent { id }.status(),
+            StatusCode::FORBIDDEN
+        );
+        let refused = anyhow::Error::from(AccountRefused("root is in the deny list".to_string()))
+            .context("Failed to reset password for root");
+        let e = ServerError::backend("root", refused);
+        assert!(matches!(e, ServerError::AccountRefused { .. }));
+        assert_eq!(e.status(), StatusCode::FORBIDDEN);
+        let e = ServerError::backend("233", anyhow::anyhow!("chpasswd failed"));
+        assert_eq!(e.page(), ("error.backend_title", "error.backend"));
+        let e = ServerError::from(anyhow::anyhow!("disk full"));
+        assert_eq!(e.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        assert_eq!(e.page(), ("error.internal_title", "error.internal"));
+    }
+}
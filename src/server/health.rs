@@ -0,0 +1,41 @@
+use crate::metrics;
+use axum::{
+    http::{header, StatusCode},
+    response::{IntoResponse, Response},
+    routing::get,
+    Router,
+};
+
+/// `/healthz`, `/readyz` and `/metrics`, outside `server.path_prefix` and not
+/// logged, as they are polled.
+pub fn health_router() -> Router {
+    Router::new()
+        .route("/healthz", get(healthz))
+        .route("/readyz", get(readyz))
+        .route("/metrics", get(metrics_handler))
+}
+
+// the process still answers
+async fn healthz() -> &'static str {
+    "ok\n"
+}
+
+async fn readyz() -> Response {
+    let problems = metrics::readiness_problems();
+    if problems.is_empty() {
+        return "ready\n".into_response();
+    }
+    let body = problems
+        .iter()
+        .map(|p| format!("{}\n", p))
+        .collect::<String>();
+    (StatusCode::SERVICE_UNAVAILABLE, body).into_response()
+}
+
+async fn metrics_handler() -> Response {
+    (
+        [(header::CONTENT_TYPE, "text/plain; version=0.0.4")],
+        metrics::render(),
+    )
+        .into_response()
+}
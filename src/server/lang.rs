@@ -0,0 +1,33 @@
+use crate::config::get_config;
+use axum::{
+    async_trait,
+    extract::{FromRequest, RequestParts},
+    http::{header, HeaderMap},
+};
+use std::convert::Infallible;
+
+/// Language of the pages, see [`request_lang`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Lang(pub String);
+
+#[async_trait]
+impl<B: Send> FromRequest<B> for Lang {
+    type Rejection = Infallible;
+
+    async fn from_request(req: &mut RequestParts<B>) -> Result<Self, Self::Rejection> {
+        Ok(Self(request_lang(req.headers())))
+    }
+}
+
+/// The language `Accept-Language` prefers among those with a catalogue,
+/// `locale.default` if none.
+pub fn request_lang(headers: &HeaderMap) -> String {
+    let config = get_config();
+    headers
+        .get_all(header::ACCEPT_LANGUAGE)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .find_map(|accept| config.catalogues.negotiate(accept))
+        .unwrap_or(&config.locale.default)
+        .to_string()
+}
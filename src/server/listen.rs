@@ -0,0 +1,109 @@
+use crate::config::{get_config, BindAddress, ServerConfig};
+use anyhow::{bail, Context, Result};
+use axum::{handler::Handler, http::Uri, response::Redirect, Router};
+use axum_server::tls_rustls::RustlsConfig;
+use once_cell::sync::OnceCell;
+use std::{net::SocketAddr, os::unix::fs::FileTypeExt};
+use tokio::net::UnixListener;
+use tracing::{error, info};
+
+// certificates served over HTTPS, set once the TLS listener is up
+static RUSTLS: OnceCell<RustlsConfig> = OnceCell::new();
+
+/// Serves `app` on `server.bind`, over HTTPS if `server.tls` is set.
+pub(super) async fn serve(app: Router) -> Result<()> {
+    let server = get_config().server.clone();
+    let bind = server.bind_address();
+    let tls = match &server.tls {
+        Some(tls) => tls,
+        None => return serve_plain(&bind, app).await,
+    };
+    if let Some(redirect_bind) = &tls.redirect_bind {
+        let redirect_bind = redirect_bind.clone();
+        let redirect = redirect_router(&server)?;
+        tokio::spawn(async move {
+            if let Err(e) = serve_plain(&redirect_bind, redirect).await {
+                error!(
+                    "HTTP redirect listener on {} stopped: {:#}",
+                    redirect_bind, e
+                );
+            }
+        });
+    }
+    let addr = match bind {
+        BindAddress::Tcp(addr) => addr,
+        BindAddress::Unix(_) => bail!("server.tls is not supported on a unix socket"),
+    };
+    let rustls = RustlsConfig::from_pem_file(&tls.cert, &tls.key)
+        .await
+        .with_context(|| format!("Failed to load {} and {}", tls.cert, tls.key))?;
+    let _ = RUSTLS.set(rustls.clone());
+    info!("listening on https://{}", addr);
+    axum_server::bind_rustls(addr, rustls)
+        .serve(app.into_make_service_with_connect_info::<SocketAddr>())
+        .await?;
+    Ok(())
+}
+
+async fn serve_plain(bind: &BindAddress, app: Router) -> Result<()> {
+    info!("listening on {}", bind);
+    match bind {
+        BindAddress::Tcp(addr) => {
+            axum::Server::try_bind(addr)?
+                .serve(app.into_make_service_with_connect_info::<SocketAddr>())
+                .await?
+        }
+        BindAddress::Unix(path) => {
+            // left behind by a previous run, anything else is not ours to remove
+            match std::fs::symlink_metadata(path) {
+                Ok(metadata) if metadata.file_type().is_socket() => std::fs::remove_file(path)
+                    .with_context(|| format!("Failed to remove {}", path.display()))?,
+                Ok(_) => bail!("{} exists and is not a socket", path.display()),
+                Err(_) => {}
+            }
+            let listener = UnixListener::bind(path)
+                .with_context(|| format!("Failed to bind {}", path.display()))?;
+            let incoming = hyper::server::accept::poll_fn(move |cx| {
+                listener
+                    .poll_accept(cx)
+                    .map(|accepted| Some(accepted.map(|(stream, _)| stream)))
+            });
+            axum::Server::builder(incoming)
+                .serve(app.into_make_service())
+                .await?
+        }
+    }
+    Ok(())
+}
+
+// redirects every request to the same path over HTTPS
+fn redirect_router(server: &ServerConfig) -> Result<Router> {
+    let mut base = url::Url::parse(&server.base_url())?;
+    if base.set_scheme("https").is_err() {
+        bail!("can't redirect to {} over HTTPS", base);
+    }
+    let origin = base.origin().ascii_serialization();
+    let redirect = move |uri: Uri| async move {
+        let path = uri.path_and_query().map_or("/", |p| p.as_str());
+        Redirect::permanent(&format!("{}{}", origin, path))
+    };
+    Ok(Router::new().fallback(redirect.into_service()))
+}
+
+/// Reloads the certificate served over HTTPS, if any.
+///
+/// The paths are read from the current config, so call it after a reload to
+/// pick up changed paths as well.
+pub async fn reload_tls() {
+    let (rustls, tls) = match (RUSTLS.get(), get_config().server.tls.clone()) {
+        (Some(rustls), Some(tls)) => (rustls, tls),
+        _ => return,
+    };
+    match rustls.reload_from_pem_file(&tls.cert, &tls.key).await {
+        Ok(()) => info!("reloaded certificate {}", tls.cert),
+        Err(e) => error!(
+            "Failed to reload certificate {}, keeping the current one: {}",
+            tls.cert, e
+        ),
+    }
+}
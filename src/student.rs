@@ -1,5 +1,5 @@
-use crate::config::get_config;
-use anyhow::Result;
+use crate::{config::get_config, metrics, payload::email_hash};
+use anyhow::{bail, Result};
 use once_cell::sync::Lazy;
 use parking_lot::RwLock;
 use std::{
@@ -9,8 +9,17 @@ use std::{
 use tracing::{debug, error, info};
 use walkdir::WalkDir;
 
-// id -> email
-static STUDENTS: Lazy<RwLock<HashMap<String, String>>> = Lazy::new(|| RwLock::new(HashMap::new()));
+/// What a student registered in the `.tenzin` file in their home: the email
+/// on the first line, optionally the language of their mails on the second.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Student {
+    email: String,
+    lang: Option<String>,
+}
+
+// tenant -> id -> student
+static STUDENTS: Lazy<RwLock<HashMap<String, HashMap<String, Student>>>> =
+    Lazy::new(|| RwLock::new(HashMap::new()));
 static THREAD_TX: Lazy<SyncSender<()>> = Lazy::new(|| {
     let (tx, rx) = sync_channel(1);
     start_student_worker(rx).expect("Failed to start student worker");
@@ -29,18 +38,38 @@ pub fn shutdown_student_worker() {
         .expect("Failed to send shutdown signal to student worker");
 }
 
-pub fn check_student_email(id: &str, email: &str) -> bool {
-    let students = STUDENTS.read();
-    if let Some(student_email) = students.get(id) {
-        if student_email == email {
-            return true;
+pub fn check_student_email(tenant: &str, id: &str, email: &str) -> bool {
+    get_student_email(tenant, id).as_deref() == Some(email)
+}
+
+pub fn get_student_email(tenant: &str, id: &str) -> Option<String> {
+    Some(STUDENTS.read().get(tenant)?.get(id)?.email.clone())
+}
+
+/// Language the student asked their mails in, if any.
+pub fn get_student_lang(tenant: &str, id: &str) -> Option<String> {
+    STUDENTS.read().get(tenant)?.get(id)?.lang.clone()
+}
+
+/// Checks a reset link was sent to the email currently registered for `id`.
+pub fn check_student_email_hash(tenant: &str, id: &str, hash: &str) -> bool {
+    let key = match get_config().sign.hash_key {
+        Some(key) => key,
+        None => {
+            error!("no sign.hash_key configured, can't check reset links");
+            return false;
         }
+    };
+    match get_student_email(tenant, id) {
+        Some(email) => email_hash(&key, &email) == hash,
+        None => false,
     }
-    false
 }
 
 fn start_student_worker(rx: Receiver<()>) -> Result<()> {
-    walk_student_dir()?;
+    if let Err(e) = walk_student_dir() {
+        error!("Failed to walk student dir: {}", e);
+    }
     std::thread::Builder::new()
         .name("t:sdtudent".to_string())
         .spawn(move || loop {
@@ -65,24 +94,48 @@ fn start_student_worker(rx: Receiver<()>) -> Result<()> {
     Ok(())
 }
 
-fn walk_student_dir() -> Result<()> {
-    let mut students = STUDENTS.write();
-    let path = &get_config().student.home_prefix;
+/// Walks the home of every tenant, a tenant failing keeps its students from
+/// the last walk and doesn't stop the others.
+pub(crate) fn walk_student_dir() -> Result<()> {
+    // rebuilt from scratch, so removed students and changed tenants take effect
+    let mut tenants = HashMap::new();
+    let mut failed = Vec::new();
+    for tenant in get_config().tenants().iter() {
+        let students = match walk_home_prefix(&tenant.home_prefix) {
+            Ok(students) => students,
+            Err(e) => {
+                error!(tenant = %tenant.name, "Failed to walk {}: {}", tenant.home_prefix, e);
+                failed.push(tenant.name.clone());
+                match STUDENTS.read().get(&tenant.name) {
+                    Some(students) => students.clone(),
+                    None => continue,
+                }
+            }
+        };
+        debug!(tenant = %tenant.name, ?students);
+        tenants.insert(tenant.name.clone(), students);
+    }
+    *STUDENTS.write() = tenants;
+    if !failed.is_empty() {
+        bail!("failed to walk tenants {}", failed.join(", "));
+    }
+    metrics::STUDENT_WALK_LAST_SUCCESS.set_now();
+    Ok(())
+}
+
+fn walk_home_prefix(path: &str) -> Result<HashMap<String, Student>> {
+    let mut students = HashMap::new();
     for entry in WalkDir::new(path).min_depth(1).max_depth(1) {
         let entry = entry?;
         let path = entry.file_name();
         let tz_config = entry.path().join(".tenzin");
         if tz_config.exists() {
-            let f = || -> anyhow::Result<String> {
-                let email = std::fs::read_to_string(&tz_config)?.trim().to_string();
-                if !validator::validate_email(&email) {
-                    return Err(anyhow::anyhow!(format!("invalid email: {}", email)));
-                }
-                Ok(email)
+            let f = || -> anyhow::Result<Student> {
+                parse_student(&std::fs::read_to_string(&tz_config)?)
             };
             match f() {
-                Ok(email) => {
-                    students.insert(entry.file_name().to_string_lossy().to_string(), email);
+                Ok(student) => {
+                    students.insert(entry.file_name().to_string_lossy().to_string(), student);
                 }
                 Err(e) => {
                     error!(
@@ -96,6 +149,31 @@ fn walk_student_dir() -> Result<()> {
             info!("{} has no .tenzin file", path.to_string_lossy());
         }
     }
-    debug!(?students);
-    Ok(())
+    Ok(students)
+}
+
+fn parse_student(s: &str) -> Result<Student> {
+    let mut lines = s.lines().map(str::trim).filter(|l| !l.is_empty());
+    let email = lines.next().unwrap_or_default().to_string();
+    if !validator::validate_email(&email) {
+        return Err(anyhow::anyhow!(format!("invalid email: {}", email)));
+    }
+    let lang = lines.next().map(str::to_lowercase);
+    Ok(Student { email, lang })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_student() -> Result<()> {
+        let student = parse_student("a@bupt.edu.cn\n")?;
+        assert_eq!(student.email, "a@bupt.edu.cn");
+        assert_eq!(student.lang, None);
+        let student = parse_student("a@bupt.edu.cn\nEN\n")?;
+        assert_eq!(student.lang.as_deref(), Some("en"));
+        assert!(parse_student("en\na@bupt.edu.cn").is_err());
+        Ok(())
+    }
 }
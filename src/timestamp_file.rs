@@ -0,0 +1,152 @@
+use crate::backend::tmp_path;
+use anyhow::{Context, Result};
+use parking_lot::{const_mutex, MappedMutexGuard, Mutex, MutexGuard};
+use std::{
+    fs::OpenOptions,
+    io::Write,
+    path::{Path, PathBuf},
+};
+use tracing::{error, info};
+
+/// A file of `<key>\t<timestamp>` lines, appended to for every entry and
+/// rewritten whole to drop old ones.
+#[derive(Debug)]
+pub struct TimestampFile {
+    path: PathBuf,
+}
+
+impl TimestampFile {
+    pub fn new(path: impl AsRef<Path>) -> Self {
+        Self {
+            path: path.as_ref().to_path_buf(),
+        }
+    }
+
+    /// Entries of the file in order, none if it doesn't exist. Invalid lines
+    /// are logged and skipped.
+    pub fn read(&self) -> Result<Vec<(String, i64)>> {
+        if !self.path.exists() {
+            return Ok(Vec::new());
+        }
+        let s = std::fs::read_to_string(&self.path)
+            .with_context(|| format!("Failed to read {}", self.path.display()))?;
+        let mut entries = Vec::new();
+        for line in s.lines() {
+            match line.split_once('\t').map(|(k, t)| (k, t.parse::<i64>())) {
+                Some((key, Ok(timestamp))) => entries.push((key.to_string(), timestamp)),
+                _ => error!("Invalid line in {}: {:?}", self.path.display(), line),
+            }
+        }
+        Ok(entries)
+    }
+
+    /// Appends an entry, `sync` waits until it's on disk.
+    ///
+    /// Control characters are dropped from `key` so it stays on its line.
+    pub fn append(&self, key: &str, timestamp: i64, sync: bool) -> Result<()> {
+        let mut f = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        writeln!(f, "{}\t{}", sanitize_key(key), timestamp)?;
+        if sync {
+            f.sync_all()?;
+        }
+        Ok(())
+    }
+
+    /// Replaces the entries of the file, through a temp file renamed over it.
+    pub fn rewrite<'a>(&self, entries: impl IntoIterator<Item = (&'a str, i64)>) -> Result<()> {
+        let mut s = String::new();
+        for (key, timestamp) in entries {
+            s.push_str(&format!("{}\t{}\n", sanitize_key(key), timestamp));
+        }
+        let tmp = tmp_path(&self.path);
+        let result = (|| -> Result<()> {
+            let mut f = std::fs::File::create(&tmp)?;
+            f.write_all(s.as_bytes())?;
+            f.sync_all()?;
+            std::fs::rename(&tmp, &self.path)?;
+            Ok(())
+        })();
+        if result.is_err() {
+            let _ = std::fs::remove_file(&tmp);
+        }
+        result
+    }
+}
+
+/// `key` without control characters, which would break its line.
+pub fn sanitize_key(key: &str) -> String {
+    key.chars().filter(|c| !c.is_control()).collect()
+}
+
+/// A store opened from a configured path, reopened when a reload changes the
+/// path.
+pub struct Reopening<T> {
+    // the store with the path it was opened for
+    inner: Mutex<Option<(String, T)>>,
+}
+
+impl<T> Reopening<T> {
+    pub const fn new() -> Self {
+        Self {
+            inner: const_mutex(None),
+        }
+    }
+
+    /// Locks the store, opening it with `open` first if none is open or it
+    /// was opened for another `path`.
+    ///
+    /// An error of `open` is returned and the next call tries again, a store
+    /// `open` returns in its place, e.g. one in memory, is kept until `path`
+    /// changes.
+    pub fn lock(
+        &self,
+        path: &str,
+        open: impl FnOnce() -> Result<T>,
+    ) -> Result<MappedMutexGuard<'_, T>> {
+        let mut inner = self.inner.lock();
+        if inner.as_ref().map(|(p, _)| p.as_str()) != Some(path) {
+            info!("opening {:?}", path);
+            // a failed open leaves none, so the next call tries again
+            *inner = None;
+            *inner = Some((path.to_string(), open()?));
+        }
+        Ok(MutexGuard::map(inner, |inner| {
+            &mut inner.as_mut().expect("opened above").1
+        }))
+    }
+}
+
+impl<T> Default for Reopening<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_timestamp_file() -> Result<()> {
+        let path = std::env::temp_dir().join(format!("tenzin-timestamps-{}", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let file = TimestampFile::new(&path);
+        assert!(file.read()?.is_empty());
+        file.append("a", 1, false)?;
+        file.append("b\t\n", 2, true)?;
+        std::fs::OpenOptions::new()
+            .append(true)
+            .open(&path)?
+            .write_all(b"garbage\n")?;
+        assert_eq!(file.read()?, [("a".to_string(), 1), ("b".to_string(), 2)]);
+
+        file.rewrite([("c", 3)])?;
+        assert_eq!(file.read()?, [("c".to_string(), 3)]);
+        assert!(!tmp_path(&path).exists());
+        std::fs::remove_file(&path)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,158 @@
+use crate::{
+    config::get_config,
+    timestamp_file::{Reopening, TimestampFile},
+};
+use anyhow::Result;
+use chrono::Utc;
+use parking_lot::MappedMutexGuard;
+use std::{collections::HashMap, path::Path};
+use tracing::{debug, error};
+
+// opened by the first `used_tokens`, so a failure is an error of that request
+static USED_TOKENS: Reopening<UsedTokenStore> = Reopening::new();
+
+fn open_configured_store() -> Result<UsedTokenStore> {
+    let config = get_config();
+    let path = &config.payload.used_token_path;
+    if path.is_empty() {
+        Ok(UsedTokenStore::in_memory())
+    } else {
+        UsedTokenStore::open(path, config.payload.outdate_seconds)
+    }
+}
+
+/// Locks the store, (re)opening it first if `used_token_path` isn't the one
+/// open.
+fn used_tokens() -> Result<MappedMutexGuard<'static, UsedTokenStore>> {
+    USED_TOKENS.lock(&get_config().payload.used_token_path, open_configured_store)
+}
+
+/// Records token ids of reset links that have already been redeemed.
+///
+/// Tokens are kept in a [`TimestampFile`] with the time of their request,
+/// those older than `outdate_seconds` are dropped when the store is opened.
+#[derive(Debug)]
+pub struct UsedTokenStore {
+    file: Option<TimestampFile>,
+    // token -> request timestamp
+    used: HashMap<String, i64>,
+}
+
+impl UsedTokenStore {
+    pub fn in_memory() -> Self {
+        Self {
+            file: None,
+            used: HashMap::new(),
+        }
+    }
+
+    pub fn open(path: impl AsRef<Path>, outdate_seconds: u64) -> Result<Self> {
+        let file = TimestampFile::new(path);
+        let deadline = Utc::now().timestamp() - outdate_seconds as i64;
+        let used = file
+            .read()?
+            .into_iter()
+            .filter(|(_, timestamp)| *timestamp >= deadline)
+            .collect();
+        let store = Self {
+            file: Some(file),
+            used,
+        };
+        store.compact()?;
+        Ok(store)
+    }
+
+    pub fn is_used(&self, token: &str) -> bool {
+        self.used.contains_key(token)
+    }
+
+    /// Marks `token` as used, returns `false` if it was already used.
+    pub fn mark_used(&mut self, token: &str, timestamp: i64) -> Result<bool> {
+        if self.is_used(token) {
+            return Ok(false);
+        }
+        if let Some(file) = &self.file {
+            file.append(token, timestamp, true)?;
+        }
+        self.used.insert(token.to_string(), timestamp);
+        Ok(true)
+    }
+
+    pub fn unmark_used(&mut self, token: &str) -> Result<()> {
+        if self.used.remove(token).is_some() {
+            self.compact()?;
+        }
+        Ok(())
+    }
+
+    fn compact(&self) -> Result<()> {
+        if let Some(file) = &self.file {
+            file.rewrite(self.used.iter().map(|(token, t)| (token.as_str(), *t)))?;
+            debug!("compacted used token store: {} entries", self.used.len());
+        }
+        Ok(())
+    }
+}
+
+// the store blocks on file IO, keep it off the async runtime
+async fn with_used_tokens<T: Send + 'static>(
+    f: impl FnOnce(&mut UsedTokenStore) -> Result<T> + Send + 'static,
+) -> Result<T> {
+    tokio::task::spawn_blocking(move || f(&mut *used_tokens()?)).await?
+}
+
+pub async fn is_token_used(token: &str) -> Result<bool> {
+    let token = token.to_string();
+    with_used_tokens(move |store| Ok(store.is_used(&token))).await
+}
+
+/// Claims `token` for a single redemption, returns `None` if it is already used.
+///
+/// The claim is persisted immediately so concurrent requests can't redeem the
+/// same link twice. Call [`TokenClaim::release`] if the redemption failed and the
+/// link should stay usable.
+pub async fn claim_token(token: &str, timestamp: i64) -> Result<Option<TokenClaim>> {
+    let token = token.to_string();
+    with_used_tokens(move |store| {
+        let claimed = store.mark_used(&token, timestamp)?;
+        Ok(claimed.then_some(TokenClaim { token }))
+    })
+    .await
+}
+
+pub struct TokenClaim {
+    token: String,
+}
+
+impl TokenClaim {
+    pub async fn release(self) {
+        let token = self.token.clone();
+        if let Err(e) = with_used_tokens(move |store| store.unmark_used(&token)).await {
+            error!("Failed to release token {}: {}", self.token, e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_used_token_store() -> Result<()> {
+        let path = std::env::temp_dir().join(format!("tenzin-used-{}", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let now = Utc::now().timestamp();
+        {
+            let mut store = UsedTokenStore::open(&path, 60)?;
+            assert!(store.mark_used("a", now)?);
+            assert!(!store.mark_used("a", now)?);
+            assert!(store.mark_used("b", now)?);
+            store.unmark_used("b")?;
+        }
+        let store = UsedTokenStore::open(&path, 60)?;
+        assert!(store.is_used("a"));
+        assert!(!store.is_used("b"));
+        std::fs::remove_file(&path)?;
+        Ok(())
+    }
+}